@@ -0,0 +1,21 @@
+use wms::{InputAttack, WMSKeyboardDevice};
+
+/// Prints the reports a script would send, e.g. `dry-run scripts/sample-script.txt --json`
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let path = args
+        .get(1)
+        .map(String::as_str)
+        .unwrap_or("scripts/sample-script.txt");
+
+    let mut kybd = WMSKeyboardDevice::new();
+    kybd.load_script(&std::fs::read_to_string(path)?)?;
+    let timeline = kybd.compile();
+    if args.iter().any(|arg| arg == "--json") {
+        println!("{}", timeline.to_json());
+    } else {
+        print!("{}", timeline);
+    }
+
+    Ok(())
+}
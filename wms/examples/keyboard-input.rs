@@ -1,13 +1,20 @@
-use usb_gadget;
-use wms::{Attack, InputAttack, SnoopAttack, WMSError, WMSKeyboardDevice};
+use wms::{
+    read_team_key, Attack, AuditLog, Bundle, InputAttack, Scope, WMSKeyboardDevice,
+    ENUMERATION_TIMEOUT,
+};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     usb_gadget::remove_all().expect("Could not remove existing USB gadget");
 
     let mut kybd = WMSKeyboardDevice::new();
+    let team_key = read_team_key("team.pub")?;
+    let scope = Scope::from_file("scope.txt")?;
+    kybd.set_authorization(scope.authorize(&std::env::var("USER")?, Some(&team_key))?)?;
+    kybd.set_audit_log(AuditLog::open("audit.log")?)?;
     let reg_gadget = kybd.setup_gadget()?;
-    kybd.read_script("scripts/sample-script.txt");
-    kybd.input_attack()?;
+    kybd.kill_switch().watch_signals()?;
+    kybd.load_bundle(&Bundle::open("payload", &team_key)?)?;
+    kybd.on_host_connected(&reg_gadget, ENUMERATION_TIMEOUT, |kybd| kybd.input_attack())?;
 
     Ok(())
 }
@@ -0,0 +1,22 @@
+use wms::{
+    read_team_key, Bundle, Composite, GadgetProfile, InputAttack, Scope, WMSKeyboardDevice,
+    WMSMassStorageDevice, ENUMERATION_TIMEOUT,
+};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    usb_gadget::remove_all().expect("Could not remove existing USB gadget");
+
+    let mut kybd = WMSKeyboardDevice::new();
+    let team_key = read_team_key("team.pub")?;
+    let scope = Scope::from_file("scope.txt")?;
+    kybd.set_authorization(scope.authorize(&std::env::var("USER")?, Some(&team_key))?)?;
+    let mut msd = WMSMassStorageDevice::new("./storage")?;
+    let reg_gadget = Composite::new(GadgetProfile::default())
+        .with(&mut kybd)
+        .with(&mut msd)
+        .bind()?;
+    kybd.load_bundle(&Bundle::open("payload", &team_key)?)?;
+    kybd.on_host_connected(&reg_gadget, ENUMERATION_TIMEOUT, |kybd| kybd.input_attack())?;
+
+    Ok(())
+}
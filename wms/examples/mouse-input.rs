@@ -0,0 +1,17 @@
+use wms::{read_team_key, Attack, Bundle, InputAttack, Scope, WMSMouseDevice, ENUMERATION_TIMEOUT};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    usb_gadget::remove_all().expect("Could not remove existing USB gadget");
+
+    let mut mouse = WMSMouseDevice::new();
+    let team_key = read_team_key("team.pub")?;
+    let scope = Scope::from_file("scope.txt")?;
+    mouse.set_authorization(scope.authorize(&std::env::var("USER")?, Some(&team_key))?)?;
+    let reg_gadget = mouse.setup_gadget()?;
+    mouse.load_bundle(&Bundle::open("mouse-payload", &team_key)?)?;
+    mouse.on_host_connected(&reg_gadget, ENUMERATION_TIMEOUT, |mouse| {
+        mouse.input_attack()
+    })?;
+
+    Ok(())
+}
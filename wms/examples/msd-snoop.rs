@@ -1,11 +1,13 @@
-use usb_gadget;
-use wms::{Attack, InputAttack, SnoopAttack, WMSError, WMSKeyboardDevice, WMSMassStorageDevice};
+use wms::{read_team_key, Attack, Scope, SnoopAttack, WMSKeyboardDevice};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     usb_gadget::remove_all().expect("Could not remove existing USB gadget");
 
     let mut msd = WMSKeyboardDevice::new();
-    let reg_gadget = msd.setup_gadget()?;
+    let team_key = read_team_key("team.pub")?;
+    let scope = Scope::from_file("scope.txt")?;
+    msd.set_authorization(scope.authorize(&std::env::var("USER")?, Some(&team_key))?)?;
+    let _reg_gadget = msd.setup_gadget()?;
     msd.open_logfile("./storage")?;
     msd.snoop_attack()?;
 
@@ -0,0 +1,383 @@
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::rawhid::hex;
+use crate::{ReportSink, WMSError};
+
+/// Hash the first entry of a log is chained to
+const GENESIS: [u8; 32] = [0u8; 32];
+
+/// Append-only, hash-chained record of what a device did, for pentest reports
+///
+/// Each line is one entry:
+///
+/// ```text
+/// <seq> <monotonic µs> <wall clock ms> <hash> <event>
+/// ```
+///
+/// The hash is the SHA-256 of the previous entry's hash followed by the rest of
+/// the line without it, so editing, removing or reordering entries breaks the
+/// chain, see [`verify_audit_log`]. Monotonic time counts from the `open` entry
+/// that starts each session, and the last handle dropped writes a `close` entry.
+///
+/// Entries are synced to disk as they are written, except for reports, which
+/// are synced with the next other entry or when the injection ends, see
+/// [`AuditLog::sync_reports`].
+///
+/// Clones share the same log, so one log can be given to several devices.
+#[derive(Clone)]
+pub struct AuditLog {
+    chain: Arc<Mutex<Chain>>,
+}
+
+struct Chain {
+    file: std::fs::File,
+    seq: u64,
+    head: [u8; 32],
+    start: Instant,
+    sync_reports: bool,
+}
+
+/// What [`verify_audit_log`] found in an intact log
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditSummary {
+    pub entries: u64,
+    /// Hash of the last entry, in hex
+    pub head: String,
+}
+
+impl AuditLog {
+    /// Opens a log, appending to it if it exists
+    ///
+    /// An existing log is verified first, so a new session is never chained to
+    /// a tampered one. A session that was never closed, e.g. because the device
+    /// lost power, is noted in the log.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<AuditLog, WMSError> {
+        let path = path.as_ref();
+        let (seq, head, closed) = match path.exists() {
+            true => read_chain(path, None)?,
+            false => (0, GENESIS, true),
+        };
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(WMSError::FileError)?;
+        let log = AuditLog {
+            chain: Arc::new(Mutex::new(Chain {
+                file,
+                seq,
+                head,
+                start: Instant::now(),
+                sync_reports: false,
+            })),
+        };
+        log.record("open")?;
+        if !closed {
+            log.record("previous session was not closed")?;
+        }
+        Ok(log)
+    }
+
+    /// Appends an event, which must fit on one line
+    pub fn record(&self, event: &str) -> Result<(), WMSError> {
+        self.chain
+            .lock()
+            .unwrap()
+            .append(event, true)
+            .map_err(WMSError::FileError)
+    }
+
+    /// Sets whether each report is synced to disk as soon as it is recorded
+    ///
+    /// Off by default, as a sync can take longer than a keystroke on SD cards
+    /// and would throw off the timing of the injection.
+    pub fn sync_reports(&self, sync: bool) {
+        self.chain.lock().unwrap().sync_reports = sync;
+    }
+
+    /// Syncs entries written so far to disk
+    pub(crate) fn sync(&self) -> Result<(), WMSError> {
+        let chain = self.chain.lock().unwrap();
+        chain.file.sync_data().map_err(WMSError::FileError)
+    }
+
+    /// Records a script being loaded, along with the SHA-256 of its contents
+    pub(crate) fn record_script(&self, path: &str, script: &str) -> Result<(), WMSError> {
+        let hash = hex(&Sha256::digest(script.as_bytes()), "");
+        self.record(&format!("script {} sha256={}", path.escape_debug(), hash))
+    }
+
+    /// Hash of the last entry written, in hex
+    ///
+    /// Noted down away from the device, it is what [`verify_audit_log`] checks
+    /// the log against.
+    pub fn head(&self) -> String {
+        hex(&self.chain.lock().unwrap().head, "")
+    }
+}
+
+impl Chain {
+    fn append(&mut self, event: &str, sync: bool) -> std::io::Result<()> {
+        let monotonic = self.start.elapsed().as_micros();
+        let wall = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let event = event.replace(['\n', '\r'], " ");
+        let hash = chain_hash(&self.head, self.seq, monotonic, wall, &event);
+        writeln!(
+            self.file,
+            "{} {} {} {} {}",
+            self.seq,
+            monotonic,
+            wall,
+            hex(&hash, ""),
+            event
+        )?;
+        if sync {
+            self.file.sync_data()?;
+        }
+        self.seq += 1;
+        self.head = hash;
+        Ok(())
+    }
+}
+
+impl Drop for Chain {
+    fn drop(&mut self) {
+        match self.append("close", true) {
+            Ok(()) => println!("Audit log closed, head {}", hex(&self.head, "")),
+            Err(e) => eprintln!("Could not close audit log: {}", e),
+        }
+    }
+}
+
+/// Syncs the reports of an injection that ended to `log`, noting if its kill switch stopped it
+pub(crate) fn note_end(
+    result: Result<(), WMSError>,
+    log: Option<&AuditLog>,
+) -> Result<(), WMSError> {
+    match (&result, log) {
+        (Err(WMSError::Aborted), Some(log)) => log.record("abort")?,
+        (_, Some(log)) => log.sync()?,
+        (_, None) => (),
+    }
+    result
+}
+
+/// Records every report sent through a sink, if there is a log
+pub(crate) struct Audited<'a> {
+    sink: &'a mut dyn ReportSink,
+    log: Option<&'a AuditLog>,
+    function: &'static str,
+}
+
+impl<'a> Audited<'a> {
+    pub(crate) fn new(
+        sink: &'a mut dyn ReportSink,
+        log: Option<&'a AuditLog>,
+        function: &'static str,
+    ) -> Audited<'a> {
+        Audited {
+            sink,
+            log,
+            function,
+        }
+    }
+}
+
+impl ReportSink for Audited<'_> {
+    fn send_report(&mut self, report: &[u8]) -> std::io::Result<()> {
+        self.sink.send_report(report)?;
+        if let Some(log) = self.log {
+            let event = format!("report {} {}", self.function, hex(report, ""));
+            let mut chain = log.chain.lock().unwrap();
+            let sync = chain.sync_reports;
+            chain.append(&event, sync)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the hash chain of a log written by [`AuditLog`] against a head noted down earlier
+///
+/// Fails with [`WMSError::AuditError`] at the first entry that was edited,
+/// removed, reordered or corrupted, or if the log does not end with a `close`
+/// entry, as happens when it was cut short or the device lost power. The chain
+/// isn't keyed, so anyone can write a new log that hashes correctly: `head`,
+/// the hash [`AuditLog::head`] gave or the log printed when it closed, is what
+/// ties the log to the one the device wrote. It must be one of the log's entries.
+pub fn verify_audit_log(
+    path: impl AsRef<std::path::Path>,
+    head: &str,
+) -> Result<AuditSummary, WMSError> {
+    let (entries, last, closed) = read_chain(path.as_ref(), Some(head))?;
+    if !closed {
+        return Err(WMSError::AuditError(
+            "log does not end with a close entry, it was truncated or not closed".to_string(),
+        ));
+    }
+    Ok(AuditSummary {
+        entries,
+        head: hex(&last, ""),
+    })
+}
+
+/// Checks the chain of a log, returning its length, last hash and whether it was closed
+fn read_chain(
+    path: &std::path::Path,
+    head: Option<&str>,
+) -> Result<(u64, [u8; 32], bool), WMSError> {
+    let file = std::fs::File::open(path).map_err(WMSError::FileError)?;
+    let mut prev = GENESIS;
+    let mut seq = 0;
+    let mut last_monotonic = 0;
+    let mut last_event = String::new();
+    let mut head_found = false;
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(WMSError::FileError)?;
+        let error = |message: &str| WMSError::AuditError(format!("line {}: {}", i + 1, message));
+        let mut fields = line.splitn(5, ' ');
+        let (Some(n), Some(monotonic), Some(wall), Some(hash), Some(event)) = (
+            fields.next().and_then(|f| f.parse::<u64>().ok()),
+            fields.next().and_then(|f| f.parse::<u128>().ok()),
+            fields.next().and_then(|f| f.parse::<u128>().ok()),
+            fields.next().and_then(from_hex),
+            fields.next(),
+        ) else {
+            return Err(error("malformed entry"));
+        };
+        if n != seq {
+            return Err(error(&format!("expected entry {}, found {}", seq, n)));
+        }
+        if hash != chain_hash(&prev, n, monotonic, wall, event) {
+            return Err(error("hash does not match, entry or chain was modified"));
+        }
+        let session_start = event == "open";
+        if !session_start && monotonic < last_monotonic {
+            return Err(error("monotonic time went backwards"));
+        }
+        prev = hash;
+        seq += 1;
+        last_monotonic = monotonic;
+        last_event = event.to_string();
+        head_found |= head.is_some_and(|head| head.eq_ignore_ascii_case(&hex(&hash, "")));
+    }
+    if seq == 0 {
+        return Err(WMSError::AuditError("log is empty".to_string()));
+    }
+    if let (Some(head), false) = (head, head_found) {
+        return Err(WMSError::AuditError(format!(
+            "head {} is not in the log",
+            head
+        )));
+    }
+    Ok((seq, prev, last_event == "close"))
+}
+
+fn chain_hash(prev: &[u8; 32], seq: u64, monotonic: u128, wall: u128, event: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update(format!("{} {} {} {}", seq, monotonic, wall, event));
+    hasher.finalize().into()
+}
+
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    crate::parse_hex(hex)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a closed log with a few entries, returning its path and the head after them
+    fn write_log(name: &str) -> (std::path::PathBuf, String) {
+        let path = std::env::temp_dir().join(format!("wms-{}-{}.log", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog::open(&path).unwrap();
+        log.record("bind").unwrap();
+        log.record("report keyboard 0000040000000000").unwrap();
+        let head = log.head();
+        drop(log);
+        (path, head)
+    }
+
+    fn rewrite(path: &std::path::Path, edit: impl FnOnce(&mut Vec<String>)) {
+        let text = std::fs::read_to_string(path).unwrap();
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        edit(&mut lines);
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn verifies_closed_log() {
+        let (path, head) = write_log("closed");
+        let summary = verify_audit_log(&path, &head).unwrap();
+        assert_eq!(summary.entries, 4);
+        assert_eq!(verify_audit_log(&path, &summary.head).unwrap(), summary);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detects_edits() {
+        let (path, head) = write_log("edited");
+        rewrite(&path, |lines| {
+            lines[2] = lines[2].replace("0000040000000000", "0000050000000000")
+        });
+        let error = verify_audit_log(&path, &head).unwrap_err();
+        assert!(matches!(error, WMSError::AuditError(message) if message.starts_with("line 3")));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detects_removed_entries() {
+        let (path, head) = write_log("removed");
+        rewrite(&path, |lines| {
+            lines.remove(1);
+        });
+        assert!(verify_audit_log(&path, &head).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detects_truncation() {
+        let (path, head) = write_log("truncated");
+        rewrite(&path, |lines| {
+            lines.pop();
+        });
+        let error = verify_audit_log(&path, &head).unwrap_err();
+        assert!(matches!(error, WMSError::AuditError(message) if message.contains("truncated")));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn requires_the_head() {
+        let (path, _) = write_log("head");
+        let (other, other_head) = write_log("other");
+        assert!(verify_audit_log(&path, &other_head).is_err());
+        assert!(verify_audit_log(&path, "").is_err());
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn reopening_continues_the_chain() {
+        let (path, _) = write_log("reopened");
+        let log = AuditLog::open(&path).unwrap();
+        let mut recorder = crate::Recorder::new();
+        Audited::new(&mut recorder, Some(&log), "mouse")
+            .send_report(&[1, 2])
+            .unwrap();
+        let head = log.head();
+        drop(log);
+        assert_eq!(verify_audit_log(&path, &head).unwrap().entries, 7);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.lines().nth(5).unwrap().ends_with(" report mouse 0102"));
+        assert_eq!(recorder.reports(), [vec![1, 2]]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
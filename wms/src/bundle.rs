@@ -0,0 +1,385 @@
+use std::path::{Component, Path, PathBuf};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::profile::read_fields;
+use crate::rawhid::hex;
+use crate::{parse_hex, AuditLog, KeyboardLayout, WMSError};
+
+/// Name of the manifest in a bundle directory
+pub const MANIFEST: &str = "manifest.txt";
+/// Name of the manifest's signature in a bundle directory
+pub const SIGNATURE: &str = "manifest.sig";
+
+/// A reviewed payload: a script, its assets and a signed manifest describing them
+///
+/// Bundles are directories holding `manifest.txt`, its ed25519 signature in
+/// `manifest.sig` (hex or raw bytes), and the files the manifest lists:
+///
+/// ```text
+/// name = exfil-demo
+/// version = 1.2.0
+/// author = jdoe
+/// layout = de
+/// functions = keyboard, mass_storage
+/// script = payload.txt <sha256>
+/// asset = loot/readme.txt <sha256>
+/// ```
+///
+/// `asset` can be repeated. The signature covers the manifest, which pins the
+/// contents of every file by its SHA-256, so [`sign_bundle`] or any ed25519
+/// tool can sign it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bundle {
+    pub manifest: Manifest,
+    dir: PathBuf,
+    script: String,
+    /// Contents of the assets, in manifest order
+    assets: Vec<Vec<u8>>,
+}
+
+/// What a bundle contains and needs, see [`Bundle`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    /// Keyboard layout of the target host, see [`KeyboardLayout::by_name`]
+    pub layout: String,
+    /// Gadget functions the payload needs, e.g. `keyboard` or `mass_storage`
+    pub functions: Vec<String>,
+    pub script: BundleFile,
+    pub assets: Vec<BundleFile>,
+}
+
+/// A file in a bundle, relative to its directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleFile {
+    pub path: String,
+    pub sha256: [u8; 32],
+}
+
+impl Bundle {
+    /// Opens a bundle, refusing it unless it is signed with `team_key` and untouched
+    pub fn open(dir: impl AsRef<Path>, team_key: &[u8; 32]) -> Result<Bundle, WMSError> {
+        Bundle::load(dir.as_ref(), Some(team_key))
+    }
+
+    /// Opens a bundle without checking it, for payloads still being written
+    ///
+    /// Only built with the `dev-bundles` feature, so field devices can't skip
+    /// the checks. Problems are printed instead.
+    #[cfg(feature = "dev-bundles")]
+    pub fn open_unverified(dir: impl AsRef<Path>) -> Result<Bundle, WMSError> {
+        Bundle::load(dir.as_ref(), None)
+    }
+
+    fn load(dir: &Path, team_key: Option<&[u8; 32]>) -> Result<Bundle, WMSError> {
+        let manifest_text = std::fs::read_to_string(dir.join(MANIFEST))
+            .map_err(|e| WMSError::BundleError(format!("{}: {}", MANIFEST, e)))?;
+        // With no key, problems are only reported
+        let check = |result: Result<(), WMSError>| match (result, team_key) {
+            (Err(e), None) => {
+                println!("Development bundle: {}", e);
+                Ok(())
+            }
+            (result, _) => result,
+        };
+
+        check(match team_key {
+            Some(key) => verify_signature(dir, &manifest_text, key),
+            None => Err(WMSError::BundleUnsigned),
+        })?;
+        let manifest = Manifest::parse(&manifest_text)?;
+        let read = |file: &BundleFile| {
+            let contents = std::fs::read(dir.join(&file.path))
+                .map_err(|e| WMSError::BundleError(format!("{}: {}", file.path, e)))?;
+            if <[u8; 32]>::from(Sha256::digest(&contents)) != file.sha256 {
+                check(Err(WMSError::BundleTampered(file.path.clone())))?;
+            }
+            Ok(contents)
+        };
+        // Files are kept as the bytes that were hashed, so they can't be swapped afterwards
+        let assets = manifest.assets.iter().map(read).collect::<Result<_, _>>()?;
+        let script = String::from_utf8(read(&manifest.script)?)
+            .map_err(|_| WMSError::BundleError(format!("{}: not UTF-8", manifest.script.path)))?;
+        println!(
+            "Opened bundle {} {} by {}",
+            manifest.name, manifest.version, manifest.author
+        );
+        Ok(Bundle {
+            manifest,
+            dir: dir.to_path_buf(),
+            script,
+            assets,
+        })
+    }
+
+    /// The payload script
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    /// Path the script was read from, for logs
+    pub fn script_path(&self) -> PathBuf {
+        self.dir.join(&self.manifest.script.path)
+    }
+
+    /// Contents of an asset listed in the manifest, as they were checked when opened
+    pub fn asset(&self, path: &str) -> Option<&[u8]> {
+        let i = self.manifest.assets.iter().position(|a| a.path == path)?;
+        Some(&self.assets[i])
+    }
+
+    /// Looks up the manifest's layout among the built-in and installed XKB layouts
+    pub fn layout(&self) -> Result<KeyboardLayout, WMSError> {
+        match KeyboardLayout::by_name(&self.manifest.layout) {
+            Some(layout) => Ok(layout),
+            None => KeyboardLayout::from_xkb_name(&self.manifest.layout),
+        }
+    }
+
+    /// Fails unless the manifest lists a gadget function, e.g. `keyboard`
+    pub fn require_function(&self, function: &str) -> Result<(), WMSError> {
+        match self.manifest.functions.iter().any(|f| f == function) {
+            true => Ok(()),
+            false => Err(WMSError::BundleError(format!(
+                "{} does not use the {} function",
+                self.manifest.name, function
+            ))),
+        }
+    }
+}
+
+impl Manifest {
+    /// Parses the `key = value` lines of a manifest
+    pub fn parse(manifest: &str) -> Result<Manifest, WMSError> {
+        let mut fields = [None, None, None, None, None];
+        let mut script = None;
+        let mut assets = Vec::new();
+        read_fields(manifest, WMSError::BundleError, |key, value| {
+            let bad_file = || {
+                format!(
+                    "expected `<path> <sha256>` inside the bundle, found `{}`",
+                    value
+                )
+            };
+            match key {
+                "name" => fields[0] = Some(value.to_string()),
+                "version" => fields[1] = Some(value.to_string()),
+                "author" => fields[2] = Some(value.to_string()),
+                "layout" => fields[3] = Some(value.to_string()),
+                "functions" => fields[4] = Some(value.to_string()),
+                "script" => script = Some(BundleFile::parse(value).ok_or_else(bad_file)?),
+                "asset" => assets.push(BundleFile::parse(value).ok_or_else(bad_file)?),
+                other => return Err(format!("unknown key `{}`", other)),
+            }
+            Ok(())
+        })?;
+        let [name, version, author, layout, functions] = fields;
+        let missing = |key: &str| WMSError::BundleError(format!("missing {}", key));
+        Ok(Manifest {
+            name: name.ok_or_else(|| missing("name"))?,
+            version: version.ok_or_else(|| missing("version"))?,
+            author: author.ok_or_else(|| missing("author"))?,
+            layout: layout.ok_or_else(|| missing("layout"))?,
+            functions: functions
+                .ok_or_else(|| missing("functions"))?
+                .split(',')
+                .map(|f| f.trim().to_lowercase())
+                .filter(|f| !f.is_empty())
+                .collect(),
+            script: script.ok_or_else(|| missing("script"))?,
+            assets,
+        })
+    }
+}
+
+impl BundleFile {
+    /// Parses `<path> <sha256>`, refusing paths that leave the bundle
+    fn parse(value: &str) -> Option<BundleFile> {
+        let (path, hash) = value.rsplit_once(char::is_whitespace)?;
+        let path = path.trim();
+        let inside = Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if path.is_empty() || !inside {
+            return None;
+        }
+        Some(BundleFile {
+            path: path.to_string(),
+            sha256: parse_hex(hash)?.try_into().ok()?,
+        })
+    }
+}
+
+/// Returns the script of a bundle for a device, which must be one of its `function`s
+///
+/// The script is recorded in `log` before the device parses it.
+pub(crate) fn checked_script<'a>(
+    bundle: &'a Bundle,
+    function: &str,
+    log: Option<&AuditLog>,
+) -> Result<&'a str, WMSError> {
+    bundle.require_function(function)?;
+    if let Some(log) = log {
+        log.record_script(&bundle.script_path().to_string_lossy(), bundle.script())?;
+    }
+    Ok(bundle.script())
+}
+
+fn verify_signature(dir: &Path, manifest: &str, team_key: &[u8; 32]) -> Result<(), WMSError> {
+    let bytes = std::fs::read(dir.join(SIGNATURE)).map_err(|_| WMSError::BundleUnsigned)?;
+    let hex = std::str::from_utf8(&bytes).ok().and_then(parse_hex);
+    let signature = hex.unwrap_or(bytes);
+    let key = VerifyingKey::from_bytes(team_key).map_err(|_| WMSError::BadBundleSignature)?;
+    let signature = Signature::from_slice(&signature).map_err(|_| WMSError::BadBundleSignature)?;
+    key.verify_strict(manifest.as_bytes(), &signature)
+        .map_err(|_| WMSError::BadBundleSignature)
+}
+
+/// Signs a reviewed bundle with the team's secret key
+///
+/// The hashes of the script and assets in the manifest are brought up to date
+/// first, then the manifest is signed into `manifest.sig`.
+pub fn sign_bundle(dir: impl AsRef<Path>, secret_key: &[u8; 32]) -> Result<(), WMSError> {
+    let dir = dir.as_ref();
+    let manifest = std::fs::read_to_string(dir.join(MANIFEST)).map_err(WMSError::FileError)?;
+    let mut signed = String::new();
+    for line in manifest.lines() {
+        let file = line
+            .split_once('=')
+            .filter(|(key, _)| matches!(key.trim(), "script" | "asset"));
+        match file {
+            Some((key, value)) => {
+                let value = value.trim();
+                // Manifests being written may not have a hash yet
+                let path = match BundleFile::parse(value) {
+                    Some(file) => file.path,
+                    None => value.to_string(),
+                };
+                let contents = std::fs::read(dir.join(&path)).map_err(WMSError::FileError)?;
+                let hash = hex(&Sha256::digest(&contents), "");
+                signed.push_str(&format!("{} = {} {}\n", key.trim(), path, hash));
+            }
+            None => {
+                signed.push_str(line);
+                signed.push('\n');
+            }
+        }
+    }
+    Manifest::parse(&signed)?;
+
+    let signature = SigningKey::from_bytes(secret_key).sign(signed.as_bytes());
+    std::fs::write(dir.join(MANIFEST), &signed).map_err(WMSError::FileError)?;
+    std::fs::write(dir.join(SIGNATURE), hex(&signature.to_bytes(), "") + "\n")
+        .map_err(WMSError::FileError)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET_KEY: [u8; 32] = [7; 32];
+
+    /// Writes and signs a bundle in a directory of its own
+    fn signed_bundle(name: &str) -> (PathBuf, [u8; 32]) {
+        let dir = std::env::temp_dir().join(format!("wms-bundle-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("loot")).unwrap();
+        std::fs::write(dir.join("payload.txt"), "STRING hello\n").unwrap();
+        std::fs::write(dir.join("loot/notes.txt"), "notes").unwrap();
+        let manifest = "name = demo\nversion = 1\nauthor = red team\nlayout = de\n\
+            functions = keyboard\nscript = payload.txt\nasset = loot/notes.txt\n";
+        std::fs::write(dir.join(MANIFEST), manifest).unwrap();
+        sign_bundle(&dir, &SECRET_KEY).unwrap();
+        let team_key = SigningKey::from_bytes(&SECRET_KEY)
+            .verifying_key()
+            .to_bytes();
+        (dir, team_key)
+    }
+
+    #[test]
+    fn opens_signed_bundle() {
+        let (dir, team_key) = signed_bundle("signed");
+        let bundle = Bundle::open(&dir, &team_key).unwrap();
+        assert_eq!(bundle.script(), "STRING hello\n");
+        assert_eq!(bundle.layout().unwrap().name(), "de");
+        assert!(bundle.require_function("keyboard").is_ok());
+        assert!(bundle.require_function("mouse").is_err());
+        assert_eq!(bundle.asset("loot/notes.txt"), Some(&b"notes"[..]));
+        assert_eq!(bundle.asset("payload.txt"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_tampered_files() {
+        let (dir, team_key) = signed_bundle("tampered");
+        std::fs::write(dir.join("payload.txt"), "STRING goodbye\n").unwrap();
+        let result = Bundle::open(&dir, &team_key);
+        assert!(matches!(result, Err(WMSError::BundleTampered(path)) if path == "payload.txt"));
+        std::fs::write(dir.join("payload.txt"), "STRING hello\n").unwrap();
+        std::fs::write(dir.join("loot/notes.txt"), "more notes").unwrap();
+        let result = Bundle::open(&dir, &team_key);
+        assert!(matches!(result, Err(WMSError::BundleTampered(path)) if path == "loot/notes.txt"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_edited_manifest() {
+        let (dir, team_key) = signed_bundle("edited");
+        let manifest = std::fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        std::fs::write(
+            dir.join(MANIFEST),
+            manifest.replace("layout = de", "layout = us"),
+        )
+        .unwrap();
+        let result = Bundle::open(&dir, &team_key);
+        assert!(matches!(result, Err(WMSError::BadBundleSignature)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_keys_and_unsigned_bundles() {
+        let (dir, _) = signed_bundle("unsigned");
+        let other_key = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes();
+        let result = Bundle::open(&dir, &other_key);
+        assert!(matches!(result, Err(WMSError::BadBundleSignature)));
+        std::fs::remove_file(dir.join(SIGNATURE)).unwrap();
+        let result = Bundle::open(&dir, &other_key);
+        assert!(matches!(result, Err(WMSError::BundleUnsigned)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn assets_are_the_bytes_that_were_checked() {
+        let (dir, team_key) = signed_bundle("assets");
+        let bundle = Bundle::open(&dir, &team_key).unwrap();
+        std::fs::write(dir.join("loot/notes.txt"), "swapped").unwrap();
+        assert_eq!(bundle.asset("loot/notes.txt"), Some(&b"notes"[..]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_bundle() {
+        let hash = "00".repeat(32);
+        let manifest = |script: &str| {
+            format!(
+                "name = a\nversion = 1\nauthor = b\nlayout = us\nfunctions = keyboard\n\
+                 script = {} {}\n",
+                script, hash
+            )
+        };
+        assert!(Manifest::parse(&manifest("payload.txt")).is_ok());
+        for script in ["../payload.txt", "/etc/passwd", "loot/../../payload.txt"] {
+            let result = Manifest::parse(&manifest(script));
+            assert!(
+                matches!(result, Err(WMSError::BundleError(_))),
+                "{}",
+                script
+            );
+        }
+    }
+}
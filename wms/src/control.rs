@@ -0,0 +1,146 @@
+/// Report ID of Consumer Page usages
+const CONSUMER_ID: u8 = 1;
+/// Report ID of Generic Desktop System Control usages
+const SYSTEM_ID: u8 = 2;
+
+/// Length of the longest report, the consumer control's
+pub(crate) const CONTROL_REPORT_LEN: u8 = 3;
+
+/// Report descriptor of a consumer control and a system control collection
+///
+/// Consumer reports are `[1, usage, usage]` with a little endian usage and
+/// system reports `[2, usage]`. A usage of 0 means nothing is pressed.
+#[rustfmt::skip]
+pub const CONTROL_REPORT_DESC: [u8; 49] = [
+    0x05, 0x0C,             // Usage Page (Consumer)
+    0x09, 0x01,             // Usage (Consumer Control)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, CONSUMER_ID,      //   Report ID
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x26, 0xFF, 0x03,       //   Logical Maximum (1023)
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x2A, 0xFF, 0x03,       //   Usage Maximum (1023)
+    0x75, 0x10,             //   Report Size (16)
+    0x95, 0x01,             //   Report Count (1)
+    0x81, 0x00,             //   Input (Data, Array, Absolute)
+    0xC0,                   // End Collection
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x80,             // Usage (System Control)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, SYSTEM_ID,        //   Report ID
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x26, 0xFF, 0x00,       //   Logical Maximum (255)
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x29, 0xFF,             //   Usage Maximum (255)
+    0x75, 0x08,             //   Report Size (8)
+    0x95, 0x01,             //   Report Count (1)
+    0x81, 0x00,             //   Input (Data, Array, Absolute)
+    0xC0,                   // End Collection
+];
+
+/// A key outside the keyboard page, such as a media key or the power button
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// A usage of the Consumer Page, e.g. 0xE9 for Volume Increment
+    Consumer(u16),
+    /// A System Control usage of the Generic Desktop Page, e.g. 0x82 for System Sleep
+    System(u8),
+}
+
+/// Script names of the controls, the DuckyScript `MK_` names come first
+///
+/// Names of keyboard keys, such as MUTE and STOP, are left to the keyboard.
+const CONTROLS: [(&str, Control); 24] = [
+    ("MK_VOLUP", Control::Consumer(0xE9)),
+    ("MK_VOLDOWN", Control::Consumer(0xEA)),
+    ("MK_MUTE", Control::Consumer(0xE2)),
+    ("MK_PP", Control::Consumer(0xCD)),
+    ("MK_NEXT", Control::Consumer(0xB5)),
+    ("MK_PREV", Control::Consumer(0xB6)),
+    ("MK_STOP", Control::Consumer(0xB7)),
+    ("MK_EJECT", Control::Consumer(0xB8)),
+    ("VOLUME_UP", Control::Consumer(0xE9)),
+    ("VOLUME_DOWN", Control::Consumer(0xEA)),
+    ("PLAY_PAUSE", Control::Consumer(0xCD)),
+    ("NEXT_TRACK", Control::Consumer(0xB5)),
+    ("PREVIOUS_TRACK", Control::Consumer(0xB6)),
+    ("EJECT", Control::Consumer(0xB8)),
+    ("BRIGHTNESS_UP", Control::Consumer(0x6F)),
+    ("BRIGHTNESS_DOWN", Control::Consumer(0x70)),
+    ("BROWSER_HOME", Control::Consumer(0x223)),
+    ("BROWSER_BACK", Control::Consumer(0x224)),
+    ("MAIL", Control::Consumer(0x18A)),
+    ("CALCULATOR", Control::Consumer(0x192)),
+    ("FILE_BROWSER", Control::Consumer(0x194)),
+    ("SYSTEM_POWER", Control::System(0x81)),
+    ("SYSTEM_SLEEP", Control::System(0x82)),
+    ("SYSTEM_WAKE", Control::System(0x83)),
+];
+
+impl Control {
+    /// Looks up a control by its name in scripts, ignoring case
+    pub fn from_name(name: &str) -> Option<Control> {
+        CONTROLS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, control)| *control)
+    }
+
+    /// Returns the preferred script name of the control, if it has one
+    pub fn name(self) -> Option<&'static str> {
+        CONTROLS
+            .iter()
+            .find(|(_, control)| *control == self)
+            .map(|(name, _)| *name)
+    }
+
+    /// Names scripts can use for controls
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        CONTROLS.iter().map(|(name, _)| *name)
+    }
+
+    /// Returns the report that presses or releases the control
+    pub fn report(self, pressed: bool) -> Vec<u8> {
+        match (self, pressed) {
+            (Control::Consumer(usage), true) => {
+                let [lo, hi] = usage.to_le_bytes();
+                vec![CONSUMER_ID, lo, hi]
+            }
+            (Control::Consumer(_), false) => vec![CONSUMER_ID, 0, 0],
+            (Control::System(usage), true) => vec![SYSTEM_ID, usage],
+            (Control::System(_), false) => vec![SYSTEM_ID, 0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            Control::from_name("mk_volup"),
+            Some(Control::Consumer(0xE9))
+        );
+        assert_eq!(
+            Control::from_name("system_sleep"),
+            Some(Control::System(0x82))
+        );
+        assert_eq!(Control::from_name("MUTE"), None);
+        assert_eq!(Control::Consumer(0xE9).name(), Some("MK_VOLUP"));
+        assert_eq!(Control::Consumer(0x6F).name(), Some("BRIGHTNESS_UP"));
+        assert_eq!(Control::Consumer(0x1).name(), None);
+    }
+
+    #[test]
+    fn reports() {
+        let mail = Control::Consumer(0x18A);
+        assert_eq!(mail.report(true), [CONSUMER_ID, 0x8A, 0x01]);
+        assert_eq!(mail.report(false), [CONSUMER_ID, 0, 0]);
+        let power = Control::System(0x81);
+        assert_eq!(power.report(true), [SYSTEM_ID, 0x81]);
+        assert_eq!(power.report(false), [SYSTEM_ID, 0]);
+        assert!(mail.report(true).len() <= CONTROL_REPORT_LEN as usize);
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::Weak;
+
+use usb_gadget::RegGadget;
+
+use crate::{audit, unbind, AuditLog, Authorization, GadgetProfile, KillSwitch, WMSError};
+
+/// Setup every device shares, given through the setters [`device_setup!`] adds
+#[derive(Default)]
+pub(crate) struct DeviceSetup {
+    pub(crate) profile: GadgetProfile,
+    pub(crate) authorization: Option<Authorization>,
+    pub(crate) audit: Option<AuditLog>,
+    pub(crate) kill_switch: KillSwitch,
+    /// Gadget the device was last bound in, see [`crate::BoundGadget::link`]
+    pub(crate) gadget: Weak<RegGadget>,
+}
+
+impl DeviceSetup {
+    /// Ends an injection, taking the gadget off the host if the kill switch aborted it
+    ///
+    /// The abort and unbinding are recorded in the audit log, if there is one.
+    pub(crate) fn end(&self, result: Result<(), WMSError>) -> Result<(), WMSError> {
+        let aborted = matches!(result, Err(WMSError::Aborted));
+        let result = audit::note_end(result, self.audit.as_ref());
+        if let (true, Some(reg)) = (aborted, self.gadget.upgrade()) {
+            unbind(&reg, self.audit.as_ref())?;
+        }
+        result
+    }
+}
+
+/// Implements [`Attack`](crate::Attack) and the shared setters for a device with a `setup` field
+///
+/// `$audited` documents what the device records in an audit log. Devices with
+/// `kill_switch` also get the setters of their [`KillSwitch`].
+macro_rules! device_setup {
+    ($device:ty, $audited:literal) => {
+        impl $device {
+            /// Sets the IDs, strings and power setup used by [`Attack::setup_gadget`]
+            pub fn set_profile(&mut self, profile: GadgetProfile) {
+                self.setup.profile = profile;
+            }
+
+            /// Allows attacks to run, see [`Scope::authorize`]
+            ///
+            /// The grant is recorded in the audit log, once the device has one.
+            pub fn set_authorization(
+                &mut self,
+                authorization: Authorization,
+            ) -> Result<(), WMSError> {
+                authorization.record(self.setup.audit.as_ref())?;
+                self.setup.authorization = Some(authorization);
+                Ok(())
+            }
+
+            #[doc = $audited]
+            ///
+            /// An authorization the device already has is recorded first.
+            pub fn set_audit_log(&mut self, log: AuditLog) -> Result<(), WMSError> {
+                if let Some(authorization) = &self.setup.authorization {
+                    authorization.record(Some(&log))?;
+                }
+                self.setup.audit = Some(log);
+                Ok(())
+            }
+        }
+
+        impl Attack for $device {
+            fn setup_gadget(&mut self) -> Result<BoundGadget, WMSError> {
+                Composite::new(self.setup.profile.clone())
+                    .audit(self.setup.audit.clone())
+                    .with(self)
+                    .bind()
+            }
+
+            fn audit_log(&self) -> Option<&AuditLog> {
+                self.setup.audit.as_ref()
+            }
+        }
+    };
+    ($device:ty, $audited:literal, kill_switch) => {
+        device_setup!($device, $audited);
+
+        impl $device {
+            /// Lets `switch` abort or pause this device's injections, see [`KillSwitch`]
+            pub fn set_kill_switch(&mut self, switch: KillSwitch) {
+                self.setup.kill_switch = switch;
+            }
+
+            /// Returns a handle to the switch that aborts or pauses this device's injections
+            pub fn kill_switch(&self) -> KillSwitch {
+                self.setup.kill_switch.clone()
+            }
+        }
+    };
+}
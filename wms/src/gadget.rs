@@ -0,0 +1,213 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use usb_gadget::function::hid::Hid;
+use usb_gadget::{default_udc, RegGadget};
+
+use crate::{AuditLog, GadgetFunction, GadgetProfile, WMSError};
+
+/// How long to wait for udev to create the device node of a HID function
+pub const DEVICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the host to configure the gadget
+pub const ENUMERATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval between checks while waiting on the kernel or udev
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Combines the functions of several devices into one gadget
+///
+/// A single device can then type a payload and serve files at the same time:
+///
+/// ```ignore
+/// let reg = Composite::new(profile).with(&mut kybd).with(&mut msd).bind()?;
+/// kybd.on_host_connected(&reg, ENUMERATION_TIMEOUT, |kybd| kybd.input_attack())?;
+/// ```
+pub struct Composite<'a> {
+    profile: GadgetProfile,
+    functions: Vec<&'a mut dyn GadgetFunction>,
+    audit: Option<AuditLog>,
+}
+
+impl<'a> Composite<'a> {
+    pub fn new(profile: GadgetProfile) -> Composite<'a> {
+        Composite {
+            profile,
+            functions: Vec::new(),
+            audit: None,
+        }
+    }
+
+    /// Records the setup and binding of the gadget in `log`, if there is one
+    pub fn audit(mut self, log: Option<AuditLog>) -> Composite<'a> {
+        self.audit = log;
+        self
+    }
+
+    /// Adds a device's function to the gadget's configuration
+    pub fn with(mut self, function: &'a mut dyn GadgetFunction) -> Composite<'a> {
+        self.functions.push(function);
+        self
+    }
+
+    /// Registers the gadget with every function and binds it to the default UDC
+    pub fn bind(mut self) -> Result<BoundGadget, WMSError> {
+        if let Some(log) = &self.audit {
+            log.record(&format!(
+                "setup_gadget vendor_id={:#06x} product_id={:#06x} functions={}",
+                self.profile.vendor_id,
+                self.profile.product_id,
+                self.functions.len()
+            ))?;
+        }
+        let mut config = self.profile.config()?;
+        for function in self.functions.iter_mut() {
+            for handle in function.build_functions()? {
+                config = config.with_function(handle);
+            }
+        }
+
+        let udc = default_udc().map_err(WMSError::GadgetSetupError)?;
+        let reg = self
+            .profile
+            .gadget()
+            .with_config(config)
+            .bind(&udc)
+            .map_err(WMSError::GadgetSetupError)?;
+
+        if let Some(log) = &self.audit {
+            log.record(&format!("bind udc={}", udc.name().to_string_lossy()))?;
+        }
+
+        let reg = BoundGadget {
+            reg: Arc::new(reg),
+            audit: self.audit.take(),
+        };
+        for function in self.functions.iter_mut() {
+            function.bound(&reg)?;
+        }
+        Ok(reg)
+    }
+}
+
+/// A gadget bound by [`Composite::bind`], which is unbound and removed when dropped
+///
+/// Dereferences to the [`RegGadget`]. With an audit log, the unbinding and
+/// removal are recorded in it, however the gadget goes away.
+pub struct BoundGadget {
+    reg: Arc<RegGadget>,
+    audit: Option<AuditLog>,
+}
+
+impl BoundGadget {
+    /// A reference to the gadget that doesn't keep it around, for devices to unbind it on abort
+    pub fn link(&self) -> Weak<RegGadget> {
+        Arc::downgrade(&self.reg)
+    }
+}
+
+impl std::ops::Deref for BoundGadget {
+    type Target = RegGadget;
+
+    fn deref(&self) -> &RegGadget {
+        &self.reg
+    }
+}
+
+impl Drop for BoundGadget {
+    fn drop(&mut self) {
+        let Some(log) = &self.audit else {
+            return;
+        };
+        // Dropping the RegGadget afterwards does both
+        let bound = matches!(self.reg.udc(), Ok(Some(_)));
+        let recorded = match bound {
+            true => log.record("unbind").and_then(|_| log.record("remove")),
+            false => log.record("remove"),
+        };
+        if let Err(e) = recorded {
+            eprintln!("Could not record gadget removal: {}", e);
+        }
+    }
+}
+
+/// Unbinds a gadget from its UDC, recording it in `log` if given
+///
+/// Does nothing if the gadget isn't bound. The gadget is removed once `reg` is
+/// dropped.
+pub fn unbind(reg: &RegGadget, log: Option<&AuditLog>) -> Result<(), WMSError> {
+    if reg.udc().map_err(WMSError::GadgetSetupError)?.is_none() {
+        return Ok(());
+    }
+    reg.bind(None).map_err(WMSError::GadgetSetupError)?;
+    if let Some(log) = log {
+        log.record("unbind")?;
+    }
+    Ok(())
+}
+
+/// Finds the `/dev/hidgN` node of a bound HID function
+///
+/// The node is looked up from the function's major and minor numbers, waiting up
+/// to `timeout` for udev to create it.
+pub fn hidg_path(hid: &Hid, timeout: Duration) -> Result<PathBuf, WMSError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(path) = hid
+            .device()
+            .ok()
+            .and_then(|(major, minor)| dev_node(major, minor))
+        {
+            if path.exists() {
+                return Ok(path);
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err(timed_out("HID device node did not appear"));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Looks up the `/dev` path of a character device in sysfs
+fn dev_node(major: u8, minor: u8) -> Option<PathBuf> {
+    let uevent =
+        std::fs::read_to_string(format!("/sys/dev/char/{}:{}/uevent", major, minor)).ok()?;
+    uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVNAME="))
+        .map(|name| PathBuf::from("/dev").join(name))
+}
+
+/// Returns the state of a UDC, such as `not attached` or `configured`
+pub fn udc_state(udc: impl AsRef<std::ffi::OsStr>) -> std::io::Result<String> {
+    let path = PathBuf::from("/sys/class/udc")
+        .join(udc.as_ref())
+        .join("state");
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
+
+/// Blocks until the host has configured the gadget, or `timeout` has passed
+pub fn wait_for_host(reg: &RegGadget, timeout: Duration) -> Result<(), WMSError> {
+    let udc = reg
+        .udc()
+        .map_err(WMSError::GadgetSetupError)?
+        .ok_or(WMSError::RuntimeError)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let state = udc_state(&udc).map_err(WMSError::GadgetSetupError)?;
+        if state == "configured" {
+            println!("Host configured {}", udc.to_string_lossy());
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(timed_out("Host did not configure the gadget"));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+pub(crate) fn timed_out(message: &str) -> WMSError {
+    WMSError::GadgetSetupError(std::io::Error::new(std::io::ErrorKind::TimedOut, message))
+}
@@ -0,0 +1,421 @@
+use std::time::Duration;
+
+use crate::led::LOCK_KEYS;
+use crate::nkro::nkro_report;
+use crate::timing::Schedule;
+use crate::{
+    Action, Chord, Control, KeyState, KeyStroke, KillSwitch, LedMonitor, LedState, LedWait,
+    MouseAction, MouseState, RawAction, ReportSink, Timing, WMSError,
+};
+
+/// How long the host gets to report a lock LED after its key was pressed
+const LED_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How [`send_actions`] paces keys and adapts them to the host
+pub(crate) struct InjectOptions<'a> {
+    pub(crate) timing: &'a Timing,
+    /// The host's lock LEDs, which actions that wait on or restore them need
+    pub(crate) leds: Option<&'a LedMonitor>,
+    /// Turn CapsLock off while text is typed and back on afterwards, if `leds`
+    /// shows the host has it on
+    pub(crate) compensate: bool,
+    /// Send N-key rollover reports instead of boot reports
+    pub(crate) nkro: bool,
+    /// Releases everything and stops when aborted, and while paused
+    pub(crate) switch: &'a KillSwitch,
+}
+
+/// Sends actions to the host as reports
+///
+/// Each press is held and each release followed by a gap, so the host sees
+/// repeated keys as separate keystrokes. Everything is released at the end.
+/// Media and power keys go to `controls`.
+pub(crate) fn send_actions<'a>(
+    sink: &'a mut dyn ReportSink,
+    controls: Option<&'a mut dyn ReportSink>,
+    actions: &[Action],
+    options: InjectOptions<'a>,
+) -> Result<(), WMSError> {
+    let mut injector = Injector {
+        sink,
+        controls,
+        state: KeyState::default(),
+        schedule: Schedule::start(options.switch),
+        saved: None,
+        before_text: None,
+        control: None,
+        options,
+    };
+    let result = actions.iter().try_for_each(|action| injector.send(action));
+    let released = injector.send_held(false);
+    result.and(released)
+}
+
+/// Sends mouse actions to the host as reports
+///
+/// Button presses are held for the timing's hold, every other report is followed
+/// by its gap. Buttons still held at the end, or when `switch` aborts, are
+/// released. Pauses leave them held, so a drag carries on where it stopped.
+pub(crate) fn send_mouse_actions(
+    sink: &mut dyn ReportSink,
+    actions: &[MouseAction],
+    timing: &Timing,
+    switch: &KillSwitch,
+) -> Result<(), WMSError> {
+    let mut state = MouseState::default();
+    let mut schedule = Schedule::start(switch);
+    let result = actions.iter().try_for_each(|action| {
+        switch.check()?;
+        for report in state.apply(action) {
+            sink.send_report(&report).map_err(WMSError::FileError)?;
+            let wait = match action {
+                MouseAction::Press(_) => timing.hold,
+                _ => timing.gap,
+            };
+            schedule.wait_out(timing.scale(wait))?;
+        }
+        if let MouseAction::Delay(ms) = action {
+            schedule.wait_out(timing.scale(Duration::from_millis(*ms)))?;
+        }
+        Ok(())
+    });
+    if state != MouseState::default() {
+        sink.send_report(&state.release_all())
+            .map_err(WMSError::FileError)?;
+    }
+    result
+}
+
+/// Sends raw reports to the host, waiting the timing's gap after each
+///
+/// Stops when `switch` aborts. Nothing is released then, as what that means
+/// depends on the report descriptor.
+pub(crate) fn send_raw_actions(
+    sink: &mut dyn ReportSink,
+    actions: &[RawAction],
+    timing: &Timing,
+    switch: &KillSwitch,
+) -> Result<(), WMSError> {
+    let mut schedule = Schedule::start(switch);
+    for action in actions {
+        switch.check()?;
+        let wait = match action {
+            RawAction::Report(report) => {
+                sink.send_report(report).map_err(WMSError::FileError)?;
+                timing.gap
+            }
+            RawAction::Delay(ms) => Duration::from_millis(*ms),
+        };
+        schedule.wait_out(timing.scale(wait))?;
+    }
+    Ok(())
+}
+
+struct Injector<'a> {
+    sink: &'a mut dyn ReportSink,
+    controls: Option<&'a mut dyn ReportSink>,
+    state: KeyState,
+    schedule: Schedule<'a>,
+    /// LEDs remembered by [`Action::SaveLockState`]
+    saved: Option<LedState>,
+    /// LEDs to put back once the text being typed is done
+    before_text: Option<LedState>,
+    /// Media or power key held down
+    control: Option<Control>,
+    options: InjectOptions<'a>,
+}
+
+impl Injector<'_> {
+    fn send(&mut self, action: &Action) -> Result<(), WMSError> {
+        self.options.switch.check()?;
+        if self.options.switch.is_paused() {
+            self.hold_while_paused()?;
+        }
+        match action {
+            Action::WaitForLeds(leds, wait) => {
+                let switch = self.options.switch;
+                self.leds()?.wait_unless_aborted(*leds, *wait, switch)?;
+                self.schedule.restart();
+            }
+            Action::SaveLockState => self.saved = Some(self.leds()?.state()),
+            Action::RestoreLockState => {
+                let Some(saved) = self.saved else {
+                    return Ok(());
+                };
+                self.set_locks(saved, LedState::from_bits(0xff))?;
+            }
+            Action::PressControl(control) | Action::ReleaseControl(control) => {
+                let pressed = matches!(action, Action::PressControl(_));
+                let Some(controls) = self.controls.as_deref_mut() else {
+                    println!("No consumer control device to send {:?} to", control);
+                    return Err(WMSError::RuntimeError);
+                };
+                controls
+                    .send_report(&control.report(pressed))
+                    .map_err(WMSError::FileError)?;
+                self.control = pressed.then_some(*control);
+                self.wait(self.options.timing.wait_after(action, true))?;
+            }
+            Action::BeginText => {
+                let Some(leds) = self.options.leds.filter(|_| self.options.compensate) else {
+                    return Ok(());
+                };
+                let current = leds.state();
+                if current.caps_lock() {
+                    let caps_off = current.bits() & !LedState::CAPS_LOCK.bits();
+                    self.set_locks(LedState::from_bits(caps_off), LedState::CAPS_LOCK)?;
+                    self.before_text = Some(current);
+                }
+            }
+            Action::EndText => {
+                if let Some(before) = self.before_text.take() {
+                    self.set_locks(before, LedState::CAPS_LOCK)?;
+                }
+            }
+            _ => {
+                let report = self.state.apply(action);
+                if let Some(report) = report {
+                    self.send_keys(&report)?;
+                }
+                self.wait(self.options.timing.wait_after(action, report.is_some()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Toggles lock keys until the host's LEDs in `mask` match `target`
+    fn set_locks(&mut self, target: LedState, mask: LedState) -> Result<(), WMSError> {
+        let current = self.leds()?.state();
+        for (led, key) in LOCK_KEYS {
+            if !mask.contains(led) || current.contains(led) == target.contains(led) {
+                continue;
+            }
+            let keys = KeyStroke::new(key.usage(), 0).chord();
+            self.send(&Action::Press(keys))?;
+            self.send(&Action::Release(keys))?;
+            let wait = if target.contains(led) {
+                LedWait::On
+            } else {
+                LedWait::Off
+            };
+            if self.leds()?.wait_timeout(led, wait, LED_TIMEOUT).is_none() {
+                println!("Host did not update LED {:#04x}", led.bits());
+            }
+        }
+        self.schedule.restart();
+        Ok(())
+    }
+
+    /// Waits as the timing says, letting go of everything while paused
+    fn wait(&mut self, wait: Duration) -> Result<(), WMSError> {
+        let mut done = self.schedule.wait(self.options.timing.scale(wait))?;
+        while !done {
+            self.hold_while_paused()?;
+            done = self.schedule.sleep()?;
+        }
+        Ok(())
+    }
+
+    /// Releases the keys held until the kill switch resumes, then presses them again
+    fn hold_while_paused(&mut self) -> Result<(), WMSError> {
+        println!("Paused");
+        let held = !self.state.held().is_empty() || self.control.is_some();
+        if held {
+            self.send_held(false)?;
+        }
+        self.schedule.pause()?;
+        if held {
+            self.send_held(true)?;
+        }
+        println!("Resumed");
+        Ok(())
+    }
+
+    /// Presses or releases every key currently held
+    fn send_held(&mut self, pressed: bool) -> Result<(), WMSError> {
+        let keys = match pressed {
+            true => self.state.held(),
+            false => Chord::default(),
+        };
+        self.send_keys(&keys)?;
+        if let (Some(control), Some(controls)) = (self.control, self.controls.as_deref_mut()) {
+            controls
+                .send_report(&control.report(pressed))
+                .map_err(WMSError::FileError)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the keys held, in the format the host expects
+    fn send_keys(&mut self, keys: &Chord) -> Result<(), WMSError> {
+        let result = match self.options.nkro {
+            true => self.sink.send_report(&nkro_report(keys)),
+            false => self.sink.send_report(&keys.boot_report()),
+        };
+        result.map_err(WMSError::FileError)
+    }
+
+    fn leds(&self) -> Result<&LedMonitor, WMSError> {
+        match self.options.leds {
+            Some(leds) => Ok(leds),
+            None => {
+                println!("No LED reports from the host to wait on");
+                Err(WMSError::RuntimeError)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_script, Recorder};
+
+    fn inject(
+        script: &str,
+        nkro: bool,
+        switch: &KillSwitch,
+    ) -> (Vec<Vec<u8>>, Result<(), WMSError>) {
+        let actions = parse_script(script).unwrap();
+        let timing = Timing::new(Duration::ZERO, Duration::ZERO);
+        let mut recorder = Recorder::new();
+        let options = InjectOptions {
+            timing: &timing,
+            leds: None,
+            compensate: false,
+            nkro,
+            switch,
+        };
+        let result = send_actions(&mut recorder, None, &actions, options);
+        (recorder.reports(), result)
+    }
+
+    #[test]
+    fn controls_go_to_their_own_sink() {
+        let actions = parse_script("STRING a\nSYSTEM_SLEEP").unwrap();
+        let timing = Timing::new(Duration::ZERO, Duration::ZERO);
+        let switch = KillSwitch::new();
+        let options = InjectOptions {
+            timing: &timing,
+            leds: None,
+            compensate: false,
+            nkro: false,
+            switch: &switch,
+        };
+        let (mut keys, mut controls) = (Recorder::new(), Recorder::new());
+        send_actions(&mut keys, Some(&mut controls), &actions, options).unwrap();
+        assert_eq!(keys.reports().len(), 3);
+        assert_eq!(controls.reports(), [vec![2, 0x82], vec![2, 0]]);
+
+        let (_, result) = inject("MK_MUTE", false, &switch);
+        assert!(matches!(result, Err(WMSError::RuntimeError)));
+    }
+
+    #[test]
+    fn repeated_keys_are_released_between() {
+        let (reports, result) = inject("STRING aa", false, &KillSwitch::new());
+        assert!(result.is_ok());
+        let a = vec![0, 0, 0x04, 0, 0, 0, 0, 0];
+        let none = vec![0; 8];
+        assert_eq!(
+            reports,
+            vec![a.clone(), none.clone(), a, none.clone(), none]
+        );
+    }
+
+    #[test]
+    fn held_keys_are_released_at_the_end() {
+        let (reports, _) = inject("HOLD shift a", false, &KillSwitch::new());
+        assert_eq!(
+            reports,
+            vec![vec![0x02, 0, 0x04, 0, 0, 0, 0, 0], vec![0; 8]]
+        );
+    }
+
+    #[test]
+    fn release_keeps_other_keys_held() {
+        let (reports, _) = inject("HOLD a b\nRELEASE a", false, &KillSwitch::new());
+        assert_eq!(reports[1], vec![0, 0, 0x05, 0, 0, 0, 0, 0]);
+    }
+
+    /// Runs a script on another thread, returning what it sent so far as it goes
+    fn spawn(
+        script: &'static str,
+        switch: &KillSwitch,
+    ) -> (Recorder, std::thread::JoinHandle<Result<(), WMSError>>) {
+        let recorder = Recorder::new();
+        let (mut sink, switch) = (recorder.clone(), switch.clone());
+        let thread = std::thread::spawn(move || {
+            let actions = parse_script(script).unwrap();
+            let timing = Timing::new(Duration::ZERO, Duration::ZERO);
+            let options = InjectOptions {
+                timing: &timing,
+                leds: None,
+                compensate: false,
+                nkro: false,
+                switch: &switch,
+            };
+            send_actions(&mut sink, None, &actions, options)
+        });
+        (recorder, thread)
+    }
+
+    fn wait_for_reports(recorder: &Recorder, count: usize) {
+        while recorder.reports().len() < count {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn abort_releases_held_keys() {
+        let switch = KillSwitch::new();
+        let (recorder, thread) = spawn("HOLD shift a\nDELAY 10000\nSTRING b", &switch);
+        wait_for_reports(&recorder, 1);
+        switch.abort();
+        assert!(matches!(thread.join().unwrap(), Err(WMSError::Aborted)));
+        let press = vec![0x02, 0, 0x04, 0, 0, 0, 0, 0];
+        assert_eq!(recorder.reports(), vec![press, vec![0; 8]]);
+
+        let (reports, result) = inject("STRING a", false, &switch);
+        assert!(matches!(result, Err(WMSError::Aborted)));
+        assert_eq!(reports, vec![vec![0; 8]]);
+    }
+
+    #[test]
+    fn pause_lets_go_until_resumed() {
+        let switch = KillSwitch::new();
+        let (recorder, thread) = spawn("HOLD a\nDELAY 100", &switch);
+        wait_for_reports(&recorder, 1);
+        switch.pause();
+        wait_for_reports(&recorder, 2);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(recorder.reports().len(), 2);
+        switch.resume();
+        thread.join().unwrap().unwrap();
+        let (a, none) = (vec![0, 0, 0x04, 0, 0, 0, 0, 0], vec![0; 8]);
+        assert_eq!(recorder.reports(), vec![a.clone(), none.clone(), a, none]);
+    }
+
+    #[test]
+    fn abort_releases_mouse_buttons() {
+        let switch = KillSwitch::new();
+        let actions = [
+            MouseAction::Press(crate::MouseButton::Left),
+            MouseAction::Delay(10_000),
+        ];
+        let recorder = Recorder::new();
+        let (mut sink, aborter) = (recorder.clone(), switch.clone());
+        let thread = std::thread::spawn(move || {
+            wait_for_reports(&recorder, 1);
+            aborter.abort();
+        });
+        let timing = Timing::new(Duration::ZERO, Duration::ZERO);
+        let result = send_mouse_actions(&mut sink, &actions, &timing, &switch);
+        assert!(matches!(result, Err(WMSError::Aborted)));
+        thread.join().unwrap();
+        assert_eq!(
+            sink.reports(),
+            [vec![1, 0x01, 0, 0, 0, 0], vec![1, 0, 0, 0, 0, 0]]
+        );
+    }
+}
@@ -0,0 +1,437 @@
+/// Defines [`Key`] along with the names scripts use for each key
+macro_rules! keys {
+    ($($variant:ident = $usage:literal, [$($name:literal),+];)*) => {
+        /// A usage on the HID Keyboard/Keypad page (0x07)
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(u8)]
+        pub enum Key {
+            $($variant = $usage,)*
+        }
+
+        impl Key {
+            /// Every key on the page, in usage ID order
+            pub const ALL: &'static [Key] = &[$(Key::$variant,)*];
+
+            /// Lowercase script names of the key, the first one is preferred
+            pub fn names(self) -> &'static [&'static str] {
+                match self {
+                    $(Key::$variant => &[$($name),+],)*
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    A = 0x04, ["a"];
+    B = 0x05, ["b"];
+    C = 0x06, ["c"];
+    D = 0x07, ["d"];
+    E = 0x08, ["e"];
+    F = 0x09, ["f"];
+    G = 0x0A, ["g"];
+    H = 0x0B, ["h"];
+    I = 0x0C, ["i"];
+    J = 0x0D, ["j"];
+    K = 0x0E, ["k"];
+    L = 0x0F, ["l"];
+    M = 0x10, ["m"];
+    N = 0x11, ["n"];
+    O = 0x12, ["o"];
+    P = 0x13, ["p"];
+    Q = 0x14, ["q"];
+    R = 0x15, ["r"];
+    S = 0x16, ["s"];
+    T = 0x17, ["t"];
+    U = 0x18, ["u"];
+    V = 0x19, ["v"];
+    W = 0x1A, ["w"];
+    X = 0x1B, ["x"];
+    Y = 0x1C, ["y"];
+    Z = 0x1D, ["z"];
+    N1 = 0x1E, ["1"];
+    N2 = 0x1F, ["2"];
+    N3 = 0x20, ["3"];
+    N4 = 0x21, ["4"];
+    N5 = 0x22, ["5"];
+    N6 = 0x23, ["6"];
+    N7 = 0x24, ["7"];
+    N8 = 0x25, ["8"];
+    N9 = 0x26, ["9"];
+    N0 = 0x27, ["0"];
+    Enter = 0x28, ["enter", "return"];
+    Escape = 0x29, ["escape", "esc"];
+    Backspace = 0x2A, ["backspace", "bksp"];
+    Tab = 0x2B, ["tab"];
+    Space = 0x2C, ["space"];
+    Minus = 0x2D, ["-", "minus"];
+    Equal = 0x2E, ["=", "equal"];
+    LeftBracket = 0x2F, ["[", "leftbracket"];
+    RightBracket = 0x30, ["]", "rightbracket"];
+    Backslash = 0x31, ["\\", "backslash"];
+    NonUsHash = 0x32, ["nonushash"];
+    Semicolon = 0x33, [";", "semicolon"];
+    Apostrophe = 0x34, ["'", "apostrophe", "quote"];
+    Grave = 0x35, ["`", "grave"];
+    Comma = 0x36, [",", "comma"];
+    Period = 0x37, [".", "period"];
+    Slash = 0x38, ["/", "slash"];
+    CapsLock = 0x39, ["capslock"];
+    F1 = 0x3A, ["f1"];
+    F2 = 0x3B, ["f2"];
+    F3 = 0x3C, ["f3"];
+    F4 = 0x3D, ["f4"];
+    F5 = 0x3E, ["f5"];
+    F6 = 0x3F, ["f6"];
+    F7 = 0x40, ["f7"];
+    F8 = 0x41, ["f8"];
+    F9 = 0x42, ["f9"];
+    F10 = 0x43, ["f10"];
+    F11 = 0x44, ["f11"];
+    F12 = 0x45, ["f12"];
+    PrintScreen = 0x46, ["printscreen", "prtsc"];
+    ScrollLock = 0x47, ["scrolllock"];
+    Pause = 0x48, ["pause", "break"];
+    Insert = 0x49, ["insert"];
+    Home = 0x4A, ["home"];
+    PageUp = 0x4B, ["pageup"];
+    Delete = 0x4C, ["delete", "del"];
+    End = 0x4D, ["end"];
+    PageDown = 0x4E, ["pagedown"];
+    Right = 0x4F, ["rightarrow", "right"];
+    Left = 0x50, ["leftarrow", "left"];
+    Down = 0x51, ["downarrow", "down"];
+    Up = 0x52, ["uparrow", "up"];
+    NumLock = 0x53, ["numlock"];
+    KeypadSlash = 0x54, ["kp_slash"];
+    KeypadAsterisk = 0x55, ["kp_asterisk"];
+    KeypadMinus = 0x56, ["kp_minus"];
+    KeypadPlus = 0x57, ["kp_plus"];
+    KeypadEnter = 0x58, ["kp_enter"];
+    Keypad1 = 0x59, ["kp_1"];
+    Keypad2 = 0x5A, ["kp_2"];
+    Keypad3 = 0x5B, ["kp_3"];
+    Keypad4 = 0x5C, ["kp_4"];
+    Keypad5 = 0x5D, ["kp_5"];
+    Keypad6 = 0x5E, ["kp_6"];
+    Keypad7 = 0x5F, ["kp_7"];
+    Keypad8 = 0x60, ["kp_8"];
+    Keypad9 = 0x61, ["kp_9"];
+    Keypad0 = 0x62, ["kp_0"];
+    KeypadPeriod = 0x63, ["kp_period"];
+    NonUsBackslash = 0x64, ["nonusbackslash"];
+    Application = 0x65, ["menu", "app", "application"];
+    Power = 0x66, ["power"];
+    KeypadEqual = 0x67, ["kp_equal"];
+    F13 = 0x68, ["f13"];
+    F14 = 0x69, ["f14"];
+    F15 = 0x6A, ["f15"];
+    F16 = 0x6B, ["f16"];
+    F17 = 0x6C, ["f17"];
+    F18 = 0x6D, ["f18"];
+    F19 = 0x6E, ["f19"];
+    F20 = 0x6F, ["f20"];
+    F21 = 0x70, ["f21"];
+    F22 = 0x71, ["f22"];
+    F23 = 0x72, ["f23"];
+    F24 = 0x73, ["f24"];
+    Execute = 0x74, ["execute"];
+    Help = 0x75, ["help"];
+    Menu = 0x76, ["keyboard_menu"];
+    Select = 0x77, ["select"];
+    Stop = 0x78, ["stop"];
+    Again = 0x79, ["again"];
+    Undo = 0x7A, ["undo"];
+    Cut = 0x7B, ["cut"];
+    Copy = 0x7C, ["copy"];
+    Paste = 0x7D, ["paste"];
+    Find = 0x7E, ["find"];
+    Mute = 0x7F, ["mute"];
+    VolumeUp = 0x80, ["volumeup"];
+    VolumeDown = 0x81, ["volumedown"];
+    LockingCapsLock = 0x82, ["locking_capslock"];
+    LockingNumLock = 0x83, ["locking_numlock"];
+    LockingScrollLock = 0x84, ["locking_scrolllock"];
+    KeypadComma = 0x85, ["kp_comma"];
+    KeypadEqualSign = 0x86, ["kp_equalsign"];
+    International1 = 0x87, ["international1", "ro"];
+    International2 = 0x88, ["international2", "katakanahiragana"];
+    International3 = 0x89, ["international3", "yen"];
+    International4 = 0x8A, ["international4", "henkan"];
+    International5 = 0x8B, ["international5", "muhenkan"];
+    International6 = 0x8C, ["international6"];
+    International7 = 0x8D, ["international7"];
+    International8 = 0x8E, ["international8"];
+    International9 = 0x8F, ["international9"];
+    Lang1 = 0x90, ["lang1", "hangeul"];
+    Lang2 = 0x91, ["lang2", "hanja"];
+    Lang3 = 0x92, ["lang3", "katakana"];
+    Lang4 = 0x93, ["lang4", "hiragana"];
+    Lang5 = 0x94, ["lang5", "zenkakuhankaku"];
+    Lang6 = 0x95, ["lang6"];
+    Lang7 = 0x96, ["lang7"];
+    Lang8 = 0x97, ["lang8"];
+    Lang9 = 0x98, ["lang9"];
+    AlternateErase = 0x99, ["alternateerase"];
+    SysReq = 0x9A, ["sysreq"];
+    Cancel = 0x9B, ["cancel"];
+    Clear = 0x9C, ["clear"];
+    Prior = 0x9D, ["prior"];
+    Return = 0x9E, ["keyboard_return"];
+    Separator = 0x9F, ["separator"];
+    Out = 0xA0, ["out"];
+    Oper = 0xA1, ["oper"];
+    ClearAgain = 0xA2, ["clearagain"];
+    CrSel = 0xA3, ["crsel"];
+    ExSel = 0xA4, ["exsel"];
+    Keypad00 = 0xB0, ["kp_00"];
+    Keypad000 = 0xB1, ["kp_000"];
+    ThousandsSeparator = 0xB2, ["thousandsseparator"];
+    DecimalSeparator = 0xB3, ["decimalseparator"];
+    CurrencyUnit = 0xB4, ["currencyunit"];
+    CurrencySubUnit = 0xB5, ["currencysubunit"];
+    KeypadLeftParen = 0xB6, ["kp_leftparen"];
+    KeypadRightParen = 0xB7, ["kp_rightparen"];
+    KeypadLeftBrace = 0xB8, ["kp_leftbrace"];
+    KeypadRightBrace = 0xB9, ["kp_rightbrace"];
+    KeypadTab = 0xBA, ["kp_tab"];
+    KeypadBackspace = 0xBB, ["kp_backspace"];
+    KeypadA = 0xBC, ["kp_a"];
+    KeypadB = 0xBD, ["kp_b"];
+    KeypadC = 0xBE, ["kp_c"];
+    KeypadD = 0xBF, ["kp_d"];
+    KeypadE = 0xC0, ["kp_e"];
+    KeypadF = 0xC1, ["kp_f"];
+    KeypadXor = 0xC2, ["kp_xor"];
+    KeypadCaret = 0xC3, ["kp_caret"];
+    KeypadPercent = 0xC4, ["kp_percent"];
+    KeypadLess = 0xC5, ["kp_less"];
+    KeypadGreater = 0xC6, ["kp_greater"];
+    KeypadAmpersand = 0xC7, ["kp_ampersand"];
+    KeypadDoubleAmpersand = 0xC8, ["kp_doubleampersand"];
+    KeypadBar = 0xC9, ["kp_bar"];
+    KeypadDoubleBar = 0xCA, ["kp_doublebar"];
+    KeypadColon = 0xCB, ["kp_colon"];
+    KeypadHash = 0xCC, ["kp_hash"];
+    KeypadSpace = 0xCD, ["kp_space"];
+    KeypadAt = 0xCE, ["kp_at"];
+    KeypadExclamation = 0xCF, ["kp_exclamation"];
+    KeypadMemoryStore = 0xD0, ["kp_memstore"];
+    KeypadMemoryRecall = 0xD1, ["kp_memrecall"];
+    KeypadMemoryClear = 0xD2, ["kp_memclear"];
+    KeypadMemoryAdd = 0xD3, ["kp_memadd"];
+    KeypadMemorySubtract = 0xD4, ["kp_memsubtract"];
+    KeypadMemoryMultiply = 0xD5, ["kp_memmultiply"];
+    KeypadMemoryDivide = 0xD6, ["kp_memdivide"];
+    KeypadPlusMinus = 0xD7, ["kp_plusminus"];
+    KeypadClear = 0xD8, ["kp_clear"];
+    KeypadClearEntry = 0xD9, ["kp_clearentry"];
+    KeypadBinary = 0xDA, ["kp_binary"];
+    KeypadOctal = 0xDB, ["kp_octal"];
+    KeypadDecimal = 0xDC, ["kp_decimal"];
+    KeypadHexadecimal = 0xDD, ["kp_hexadecimal"];
+    LeftCtrl = 0xE0, ["ctrl", "control", "lctrl"];
+    LeftShift = 0xE1, ["shift", "lshift"];
+    LeftAlt = 0xE2, ["alt", "lalt", "option"];
+    LeftGui = 0xE3, ["gui", "windows", "meta", "command", "super", "lgui"];
+    RightCtrl = 0xE4, ["rctrl"];
+    RightShift = 0xE5, ["rshift"];
+    RightAlt = 0xE6, ["ralt", "altgr"];
+    RightGui = 0xE7, ["rgui"];
+}
+
+impl Key {
+    pub fn usage(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_usage(usage: u8) -> Option<Key> {
+        Key::ALL.iter().copied().find(|key| key.usage() == usage)
+    }
+
+    /// Looks up a key by any of its script names, ignoring case
+    pub fn from_name(name: &str) -> Option<Key> {
+        let name = name.to_lowercase();
+        Key::ALL
+            .iter()
+            .copied()
+            .find(|key| key.names().contains(&name.as_str()))
+    }
+
+    pub fn name(self) -> &'static str {
+        self.names()[0]
+    }
+
+    /// Returns the bit this key sets in the modifier byte, if it is a modifier
+    pub fn modifier_bit(self) -> Option<u8> {
+        match self.usage() {
+            usage @ 0xE0..=0xE7 => Some(1 << (usage - 0xE0)),
+            _ => None,
+        }
+    }
+
+    pub fn is_modifier(self) -> bool {
+        self.modifier_bit().is_some()
+    }
+}
+
+/// Usage reported in every key slot of a boot report when too many keys are held
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Modifiers and any number of other keys, pressed or released together
+///
+/// Keys are kept as a bitmap of usage IDs below the modifiers, the layout of an
+/// N-key rollover report, so a chord isn't limited to the six key slots of a
+/// boot report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Chord {
+    modifiers: u8,
+    keys: [u8; 28],
+}
+
+impl Chord {
+    /// A chord of modifier bits and usage IDs, modifier usages set their bit
+    pub fn new(modifiers: u8, usages: &[u8]) -> Chord {
+        let mut chord = Chord {
+            modifiers,
+            ..Chord::default()
+        };
+        for &usage in usages {
+            chord.press(usage);
+        }
+        chord
+    }
+
+    /// The keys of a boot protocol report
+    pub fn from_report(report: &[u8; 8]) -> Chord {
+        Chord::new(report[0], &report[2..])
+    }
+
+    pub fn modifiers(&self) -> u8 {
+        self.modifiers
+    }
+
+    /// Usage IDs of the keys other than modifiers, in order
+    pub fn usages(&self) -> impl Iterator<Item = u8> + '_ {
+        (1..0xE0u8).filter(|&usage| self.contains(usage))
+    }
+
+    /// Number of keys other than modifiers
+    pub fn len(&self) -> usize {
+        self.keys
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns whether the chord holds neither keys nor modifiers
+    pub fn is_empty(&self) -> bool {
+        *self == Chord::default()
+    }
+
+    pub fn contains(&self, usage: u8) -> bool {
+        match usage {
+            0..=0xDF => self.keys[usage as usize / 8] & (1 << (usage % 8)) != 0,
+            0xE0..=0xE7 => self.modifiers & (1 << (usage - 0xE0)) != 0,
+            _ => false,
+        }
+    }
+
+    /// Adds a key, usage 0 is no key and usages past the modifiers are ignored
+    pub fn press(&mut self, usage: u8) {
+        match usage {
+            0 => (),
+            1..=0xDF => self.keys[usage as usize / 8] |= 1 << (usage % 8),
+            0xE0..=0xE7 => self.modifiers |= 1 << (usage - 0xE0),
+            _ => (),
+        }
+    }
+
+    /// The keys of both chords
+    pub fn union(&self, other: &Chord) -> Chord {
+        let mut chord = *self;
+        chord.modifiers |= other.modifiers;
+        for (bits, other) in chord.keys.iter_mut().zip(other.keys) {
+            *bits |= other;
+        }
+        chord
+    }
+
+    /// The keys of this chord that aren't in `other`
+    pub fn without(&self, other: &Chord) -> Chord {
+        let mut chord = *self;
+        chord.modifiers &= !other.modifiers;
+        for (bits, other) in chord.keys.iter_mut().zip(other.keys) {
+            *bits &= !other;
+        }
+        chord
+    }
+
+    /// The chord as a boot protocol report
+    ///
+    /// With more than six keys the slots all hold ErrorRollOver, which tells
+    /// the host that too many keys are down to say which.
+    pub fn boot_report(&self) -> [u8; 8] {
+        let mut report = [self.modifiers, 0, 0, 0, 0, 0, 0, 0];
+        match self.len() {
+            0..=6 => self
+                .usages()
+                .zip(&mut report[2..])
+                .for_each(|(usage, slot)| *slot = usage),
+            _ => report[2..].fill(ERROR_ROLL_OVER),
+        }
+        report
+    }
+
+    /// The key bitmap, one bit for each usage ID from 0x00 to 0xDF
+    pub(crate) fn bitmap(&self) -> &[u8; 28] {
+        &self.keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usages_are_sorted_and_unique() {
+        assert!(Key::ALL
+            .windows(2)
+            .all(|keys| keys[0].usage() < keys[1].usage()));
+        for key in Key::ALL {
+            assert_eq!(Key::from_usage(key.usage()), Some(*key));
+        }
+        assert_eq!(Key::from_usage(0x00), None);
+    }
+
+    #[test]
+    fn names_are_unique_and_lowercase() {
+        let mut names: Vec<&str> = Key::ALL
+            .iter()
+            .flat_map(|key| key.names())
+            .copied()
+            .collect();
+        assert!(names.iter().all(|name| *name == name.to_lowercase()));
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    #[test]
+    fn lookups() {
+        assert_eq!(Key::from_name("F24"), Some(Key::F24));
+        assert_eq!(Key::from_name("app"), Some(Key::Application));
+        assert_eq!(Key::from_name("Windows"), Some(Key::LeftGui));
+        assert_eq!(Key::from_name("nope"), None);
+        assert_eq!(Key::KeypadEnter.name(), "kp_enter");
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(Key::LeftCtrl.modifier_bit(), Some(0x01));
+        assert_eq!(Key::RightAlt.modifier_bit(), Some(0x40));
+        assert_eq!(Key::RightGui.modifier_bit(), Some(0x80));
+        assert!(!Key::A.is_modifier());
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::WMSError;
+
+/// How often a watched file is checked for
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Stops or pauses a running injection from another thread, a signal or a file
+///
+/// Clones share the same switch, so one can be handed to a device and another
+/// kept to trigger it. When aborted, devices release every key and button
+/// they hold and return [`WMSError::Aborted`]. While paused they let go of
+/// keys and press them again on resume, so the host doesn't repeat them.
+#[derive(Debug, Clone, Default)]
+pub struct KillSwitch {
+    shared: Arc<(Mutex<Switch>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct Switch {
+    aborted: bool,
+    paused: bool,
+}
+
+impl KillSwitch {
+    pub fn new() -> KillSwitch {
+        KillSwitch::default()
+    }
+
+    /// Stops injections as soon as possible, this one and any started later
+    pub fn abort(&self) {
+        self.set(|switch| switch.aborted = true);
+    }
+
+    pub fn pause(&self) {
+        self.set(|switch| switch.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.set(|switch| switch.paused = false);
+    }
+
+    /// Clears an abort, so devices can inject again
+    pub fn reset(&self) {
+        self.set(|switch| *switch = Switch::default());
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.shared.0.lock().unwrap().aborted
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.0.lock().unwrap().paused
+    }
+
+    /// Aborts on SIGINT or SIGTERM, pauses on SIGUSR1 and resumes on SIGUSR2
+    ///
+    /// A second SIGINT or SIGTERM ends the process, in case nothing was running
+    /// to be aborted. Aborted devices unbind their gadget, see
+    /// [`InputAttack::input_attack`](crate::InputAttack::input_attack).
+    pub fn watch_signals(&self) -> Result<(), WMSError> {
+        use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+        let mut signals = signal_hook::iterator::Signals::new([SIGINT, SIGTERM, SIGUSR1, SIGUSR2])
+            .map_err(WMSError::FileError)?;
+        let switch = self.clone();
+        std::thread::spawn(move || {
+            for signal in signals.forever() {
+                match signal {
+                    SIGUSR1 => switch.pause(),
+                    SIGUSR2 => switch.resume(),
+                    _ if switch.is_aborted() => std::process::exit(128 + signal),
+                    _ => {
+                        println!("Aborting, signal again to quit");
+                        switch.abort();
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Aborts once a file exists at `path`, e.g. one created over SSH
+    pub fn watch_file(&self, path: impl AsRef<std::path::Path>) {
+        let path = path.as_ref().to_path_buf();
+        let switch = self.clone();
+        std::thread::spawn(move || {
+            while !path.exists() {
+                std::thread::sleep(WATCH_INTERVAL);
+            }
+            println!("Aborting, {} appeared", path.display());
+            switch.abort();
+        });
+    }
+
+    /// Fails with [`WMSError::Aborted`] once the switch has been triggered
+    pub(crate) fn check(&self) -> Result<(), WMSError> {
+        match self.is_aborted() {
+            true => Err(WMSError::Aborted),
+            false => Ok(()),
+        }
+    }
+
+    /// Sleeps until `deadline`, returning `false` early if paused
+    pub(crate) fn sleep_until(&self, deadline: Instant) -> Result<bool, WMSError> {
+        let (lock, changed) = &*self.shared;
+        let mut switch = lock.lock().unwrap();
+        loop {
+            if switch.aborted {
+                return Err(WMSError::Aborted);
+            }
+            if switch.paused {
+                return Ok(false);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(true);
+            }
+            switch = changed.wait_timeout(switch, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Blocks while paused
+    pub(crate) fn wait_resumed(&self) -> Result<(), WMSError> {
+        let (lock, changed) = &*self.shared;
+        let switch = lock.lock().unwrap();
+        let switch = changed
+            .wait_while(switch, |s| s.paused && !s.aborted)
+            .unwrap();
+        match switch.aborted {
+            true => Err(WMSError::Aborted),
+            false => Ok(()),
+        }
+    }
+
+    fn set(&self, update: impl FnOnce(&mut Switch)) {
+        let (lock, changed) = &*self.shared;
+        update(&mut lock.lock().unwrap());
+        changed.notify_all();
+    }
+}
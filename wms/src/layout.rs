@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+
+use crate::{Action, Chord, KeyState, WMSError};
+
+const SHIFT: u8 = 0x02;
+/// AltGr is the right alt key
+const ALTGR: u8 = 0x40;
+const SPACE: u8 = 0x2C;
+
+/// No character on this level of the key
+pub(crate) const N: char = '\0';
+
+/// A usage ID and the modifiers held while it is pressed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyStroke {
+    pub usage: u8,
+    pub modifiers: u8,
+}
+
+impl KeyStroke {
+    pub fn new(usage: u8, modifiers: u8) -> KeyStroke {
+        KeyStroke { usage, modifiers }
+    }
+
+    /// Converts the keystroke to a boot protocol HID report
+    pub fn report(&self) -> [u8; 8] {
+        [self.modifiers, 0, self.usage, 0, 0, 0, 0, 0]
+    }
+
+    /// The keys pressed for the keystroke
+    pub fn chord(&self) -> Chord {
+        Chord::new(self.modifiers, &[self.usage])
+    }
+}
+
+/// How to type a single character
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    pub stroke: KeyStroke,
+    /// Dead key that has to be typed before `stroke`, e.g. for accented characters
+    pub dead_key: Option<KeyStroke>,
+}
+
+/// Maps characters to the keystrokes that type them on a host keyboard layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardLayout {
+    name: String,
+    map: HashMap<char, Mapping>,
+}
+
+/// Usage ID followed by the unshifted, shifted and AltGr characters
+pub(crate) type Row = (u8, [char; 3]);
+/// Usage ID followed by the characters on each level: plain, shift, AltGr and shift+AltGr
+pub(crate) type Levels = (u8, [char; 4]);
+/// Usage ID and modifiers of a dead key, followed by the accent it adds
+pub(crate) type Dead = (u8, u8, char);
+
+/// Modifiers held for each level of [`Levels`]
+pub(crate) const LEVEL_MODIFIERS: [u8; 4] = [0, SHIFT, ALTGR, SHIFT | ALTGR];
+
+const NAMES: [&str; 9] = ["us", "gb", "uk", "de", "fr", "se", "fi", "no", "dk"];
+
+pub(crate) const COMMON: [Row; 3] = [
+    (0x28, ['\n', N, N]),
+    (0x2B, ['\t', N, N]),
+    (SPACE, [' ', N, N]),
+];
+
+const US: [Row; 21] = [
+    (0x1E, ['1', '!', N]),
+    (0x1F, ['2', '@', N]),
+    (0x20, ['3', '#', N]),
+    (0x21, ['4', '$', N]),
+    (0x22, ['5', '%', N]),
+    (0x23, ['6', '^', N]),
+    (0x24, ['7', '&', N]),
+    (0x25, ['8', '*', N]),
+    (0x26, ['9', '(', N]),
+    (0x27, ['0', ')', N]),
+    (0x2D, ['-', '_', N]),
+    (0x2E, ['=', '+', N]),
+    (0x2F, ['[', '{', N]),
+    (0x30, [']', '}', N]),
+    (0x31, ['\\', '|', N]),
+    (0x33, [';', ':', N]),
+    (0x34, ['\'', '"', N]),
+    (0x35, ['`', '~', N]),
+    (0x36, [',', '<', N]),
+    (0x37, ['.', '>', N]),
+    (0x38, ['/', '?', N]),
+];
+
+const GB: [Row; 22] = [
+    (0x1E, ['1', '!', N]),
+    (0x1F, ['2', '"', N]),
+    (0x20, ['3', '£', N]),
+    (0x21, ['4', '$', '€']),
+    (0x22, ['5', '%', N]),
+    (0x23, ['6', '^', N]),
+    (0x24, ['7', '&', N]),
+    (0x25, ['8', '*', N]),
+    (0x26, ['9', '(', N]),
+    (0x27, ['0', ')', N]),
+    (0x2D, ['-', '_', N]),
+    (0x2E, ['=', '+', N]),
+    (0x2F, ['[', '{', N]),
+    (0x30, [']', '}', N]),
+    (0x32, ['#', '~', N]),
+    (0x33, [';', ':', N]),
+    (0x34, ['\'', '@', N]),
+    (0x35, ['`', '¬', '¦']),
+    (0x36, [',', '<', N]),
+    (0x37, ['.', '>', N]),
+    (0x38, ['/', '?', N]),
+    (0x64, ['\\', '|', N]),
+];
+
+const DE: [Row; 26] = [
+    (0x08, ['e', 'E', '€']),
+    (0x10, ['m', 'M', 'µ']),
+    (0x14, ['q', 'Q', '@']),
+    (0x1C, ['z', 'Z', N]),
+    (0x1D, ['y', 'Y', N]),
+    (0x1E, ['1', '!', N]),
+    (0x1F, ['2', '"', '²']),
+    (0x20, ['3', '§', '³']),
+    (0x21, ['4', '$', N]),
+    (0x22, ['5', '%', N]),
+    (0x23, ['6', '&', N]),
+    (0x24, ['7', '/', '{']),
+    (0x25, ['8', '(', '[']),
+    (0x26, ['9', ')', ']']),
+    (0x27, ['0', '=', '}']),
+    (0x2D, ['ß', '?', '\\']),
+    (0x2F, ['ü', 'Ü', N]),
+    (0x30, ['+', '*', '~']),
+    (0x32, ['#', '\'', N]),
+    (0x33, ['ö', 'Ö', N]),
+    (0x34, ['ä', 'Ä', N]),
+    (0x35, [N, '°', N]),
+    (0x36, [',', ';', N]),
+    (0x37, ['.', ':', N]),
+    (0x38, ['-', '_', N]),
+    (0x64, ['<', '>', '|']),
+];
+
+const DE_DEAD: [Dead; 3] = [(0x2E, 0, '´'), (0x2E, SHIFT, '`'), (0x35, 0, '^')];
+
+const FR: [Row; 27] = [
+    (0x04, ['q', 'Q', N]),
+    (0x08, ['e', 'E', '€']),
+    (0x10, [',', '?', N]),
+    (0x14, ['a', 'A', N]),
+    (0x1A, ['z', 'Z', N]),
+    (0x1D, ['w', 'W', N]),
+    (0x33, ['m', 'M', N]),
+    (0x1E, ['&', '1', N]),
+    (0x1F, ['é', '2', N]),
+    (0x20, ['"', '3', '#']),
+    (0x21, ['\'', '4', '{']),
+    (0x22, ['(', '5', '[']),
+    (0x23, ['-', '6', '|']),
+    (0x24, ['è', '7', N]),
+    (0x25, ['_', '8', '\\']),
+    (0x26, ['ç', '9', '^']),
+    (0x27, ['à', '0', '@']),
+    (0x2D, [')', '°', ']']),
+    (0x2E, ['=', '+', '}']),
+    (0x30, ['$', '£', '¤']),
+    (0x32, ['*', 'µ', N]),
+    (0x34, ['ù', '%', N]),
+    (0x35, ['²', N, N]),
+    (0x36, [';', '.', N]),
+    (0x37, [':', '/', N]),
+    (0x38, ['!', '§', N]),
+    (0x64, ['<', '>', N]),
+];
+
+const FR_DEAD: [Dead; 4] = [
+    (0x2F, 0, '^'),
+    (0x2F, SHIFT, '¨'),
+    (0x1F, ALTGR, '~'),
+    (0x24, ALTGR, '`'),
+];
+
+/// Swedish and Finnish, Norwegian and Danish are built on top of this
+const SE: [Row; 21] = [
+    (0x08, ['e', 'E', '€']),
+    (0x10, ['m', 'M', 'µ']),
+    (0x1E, ['1', '!', N]),
+    (0x1F, ['2', '"', '@']),
+    (0x20, ['3', '#', '£']),
+    (0x21, ['4', '¤', '$']),
+    (0x22, ['5', '%', '€']),
+    (0x23, ['6', '&', N]),
+    (0x24, ['7', '/', '{']),
+    (0x25, ['8', '(', '[']),
+    (0x26, ['9', ')', ']']),
+    (0x27, ['0', '=', '}']),
+    (0x2D, ['+', '?', '\\']),
+    (0x2F, ['å', 'Å', N]),
+    (0x32, ['\'', '*', N]),
+    (0x33, ['ö', 'Ö', N]),
+    (0x34, ['ä', 'Ä', N]),
+    (0x35, ['§', '½', N]),
+    (0x36, [',', ';', N]),
+    (0x37, ['.', ':', N]),
+    (0x38, ['-', '_', N]),
+];
+
+const SE_EXTRA: [Row; 1] = [(0x64, ['<', '>', '|'])];
+
+const SE_DEAD: [Dead; 5] = [
+    (0x2E, 0, '´'),
+    (0x2E, SHIFT, '`'),
+    (0x30, 0, '¨'),
+    (0x30, SHIFT, '^'),
+    (0x30, ALTGR, '~'),
+];
+
+const NO: [Row; 5] = [
+    (0x2D, ['+', '?', N]),
+    (0x2E, ['\\', N, N]),
+    (0x33, ['ø', 'Ø', N]),
+    (0x34, ['æ', 'Æ', N]),
+    (0x35, ['|', '§', N]),
+];
+
+const NO_DEAD: [Dead; 5] = [
+    (0x2E, SHIFT, '`'),
+    (0x2E, ALTGR, '´'),
+    (0x30, 0, '¨'),
+    (0x30, SHIFT, '^'),
+    (0x30, ALTGR, '~'),
+];
+
+const DK: [Row; 6] = [
+    (0x2D, ['+', '?', N]),
+    (0x2E, [N, N, '|']),
+    (0x33, ['æ', 'Æ', N]),
+    (0x34, ['ø', 'Ø', N]),
+    (0x35, ['½', '§', N]),
+    (0x64, ['<', '>', '\\']),
+];
+
+/// Accent, the characters it can be combined with and the results
+const COMPOSE: [(char, &str, &str); 5] = [
+    ('`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    ('´', "aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+    ('^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    ('~', "anoANO", "ãñõÃÑÕ"),
+    ('¨', "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+];
+
+impl KeyboardLayout {
+    /// Creates a layout with no characters, to be filled in with [`KeyboardLayout::insert`]
+    pub fn empty(name: &str) -> KeyboardLayout {
+        KeyboardLayout {
+            name: name.to_string(),
+            map: HashMap::new(),
+        }
+    }
+
+    /// US ANSI, the layout used by `string_to_report`
+    pub fn us() -> KeyboardLayout {
+        KeyboardLayout::build("us", &[&US], &[])
+    }
+
+    /// UK ISO
+    pub fn gb() -> KeyboardLayout {
+        KeyboardLayout::build("gb", &[&GB], &[])
+    }
+
+    /// German QWERTZ
+    pub fn de() -> KeyboardLayout {
+        KeyboardLayout::build("de", &[&DE], &DE_DEAD)
+    }
+
+    /// French AZERTY
+    pub fn fr() -> KeyboardLayout {
+        KeyboardLayout::build("fr", &[&FR], &FR_DEAD)
+    }
+
+    /// Swedish, also used in Finland
+    pub fn se() -> KeyboardLayout {
+        KeyboardLayout::build("se", &[&SE, &SE_EXTRA], &SE_DEAD)
+    }
+
+    /// Norwegian
+    pub fn no() -> KeyboardLayout {
+        KeyboardLayout::build("no", &[&SE, &SE_EXTRA, &NO], &NO_DEAD)
+    }
+
+    /// Danish
+    pub fn dk() -> KeyboardLayout {
+        KeyboardLayout::build("dk", &[&SE, &DK], &SE_DEAD)
+    }
+
+    /// Looks up a built-in layout by its XKB style name, e.g. "de" or "gb"
+    pub fn by_name(name: &str) -> Option<KeyboardLayout> {
+        match name.to_lowercase().as_str() {
+            "us" => Some(KeyboardLayout::us()),
+            "gb" | "uk" => Some(KeyboardLayout::gb()),
+            "de" => Some(KeyboardLayout::de()),
+            "fr" => Some(KeyboardLayout::fr()),
+            "se" | "fi" => Some(KeyboardLayout::se()),
+            "no" => Some(KeyboardLayout::no()),
+            "dk" => Some(KeyboardLayout::dk()),
+            _ => None,
+        }
+    }
+
+    /// Names accepted by [`KeyboardLayout::by_name`]
+    pub fn names() -> &'static [&'static str] {
+        &NAMES
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns how to type `c`, or `None` if the layout cannot type it
+    pub fn get(&self, c: char) -> Option<&Mapping> {
+        self.map.get(&c)
+    }
+
+    /// Adds or replaces the mapping for a character
+    pub fn insert(&mut self, c: char, mapping: Mapping) {
+        self.map.insert(c, mapping);
+    }
+
+    /// Returns the presses and releases that type `c`
+    ///
+    /// Modifiers such as shift or AltGr are pressed on their own before the key,
+    /// as some hosts ignore a modifier that arrives in the same report as the key.
+    pub fn actions(&self, c: char) -> Option<Vec<Action>> {
+        let mapping = self.get(c)?;
+        let mut actions = Vec::new();
+        for stroke in mapping.dead_key.iter().chain([&mapping.stroke]) {
+            if stroke.modifiers != 0 {
+                actions.push(Action::Press(Chord::new(stroke.modifiers, &[])));
+            }
+            actions.push(Action::Press(stroke.chord()));
+            actions.push(Action::Release(stroke.chord()));
+        }
+        Some(actions)
+    }
+
+    /// Returns the presses and releases that type `text`, with shift or AltGr where needed
+    pub fn text_to_actions(&self, text: &str) -> Result<Vec<Action>, WMSError> {
+        let mut actions = Vec::new();
+        for c in text.chars() {
+            let typed = self.actions(c).ok_or_else(|| {
+                WMSError::LayoutError(format!("cannot type {:?} on the {} layout", c, self.name))
+            })?;
+            actions.extend(typed);
+        }
+        Ok(actions)
+    }
+
+    /// Returns the reports that type `text`, starting with no keys held
+    pub fn text_to_reports(&self, text: &str) -> Result<Vec<[u8; 8]>, WMSError> {
+        let mut state = KeyState::default();
+        let actions = self.text_to_actions(text)?;
+        Ok(actions
+            .iter()
+            .filter_map(|a| state.apply(a))
+            .map(|keys| keys.boot_report())
+            .collect())
+    }
+
+    /// Builds a layout from rows of keys, later rows replace earlier ones with the same usage ID
+    fn build(name: &str, rows: &[&[Row]], dead_keys: &[Dead]) -> KeyboardLayout {
+        let mut keys: Vec<Row> = (0..26u8)
+            .map(|i| {
+                let c = (b'a' + i) as char;
+                (0x04 + i, [c, c.to_ascii_uppercase(), N])
+            })
+            .collect();
+        keys.extend_from_slice(&COMMON);
+        for row in rows.iter().flat_map(|rows| rows.iter()) {
+            keys.retain(|(usage, _)| *usage != row.0);
+            keys.push(*row);
+        }
+        let keys: Vec<Levels> = keys
+            .into_iter()
+            .map(|(usage, [plain, shift, altgr])| (usage, [plain, shift, altgr, N]))
+            .collect();
+        KeyboardLayout::from_levels(name, &keys, dead_keys)
+    }
+
+    /// Builds a layout from the characters on each level of each key and its dead keys
+    pub(crate) fn from_levels(name: &str, keys: &[Levels], dead_keys: &[Dead]) -> KeyboardLayout {
+        let mut layout = KeyboardLayout::empty(name);
+        // Prefer the mapping with the fewest modifiers when a character appears twice
+        for (level, modifiers) in LEVEL_MODIFIERS.into_iter().enumerate() {
+            for (usage, chars) in keys {
+                let c = chars[level];
+                if c != N && layout.get(c).is_none() {
+                    let stroke = KeyStroke::new(*usage, modifiers);
+                    layout.insert(
+                        c,
+                        Mapping {
+                            stroke,
+                            dead_key: None,
+                        },
+                    );
+                }
+            }
+        }
+
+        for &(usage, modifiers, accent) in dead_keys {
+            let dead_key = Some(KeyStroke::new(usage, modifiers));
+            if layout.get(accent).is_none() {
+                // Typing the dead key followed by space produces the accent on its own
+                let stroke = KeyStroke::new(SPACE, 0);
+                layout.insert(accent, Mapping { stroke, dead_key });
+            }
+            let Some((_, bases, composed)) = COMPOSE.iter().find(|(a, _, _)| *a == accent) else {
+                continue;
+            };
+            for (base, c) in bases.chars().zip(composed.chars()) {
+                if layout.get(c).is_some() {
+                    continue;
+                }
+                if let Some(&Mapping {
+                    stroke,
+                    dead_key: None,
+                }) = layout.get(base)
+                {
+                    layout.insert(c, Mapping { stroke, dead_key });
+                }
+            }
+        }
+        layout
+    }
+}
+
+impl Default for KeyboardLayout {
+    fn default() -> KeyboardLayout {
+        KeyboardLayout::us()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(layout: &KeyboardLayout, c: char) -> (u8, u8) {
+        let mapping = layout.get(c).unwrap();
+        (mapping.stroke.usage, mapping.stroke.modifiers)
+    }
+
+    #[test]
+    fn names() {
+        for name in KeyboardLayout::names() {
+            assert!(KeyboardLayout::by_name(name).is_some(), "{}", name);
+        }
+        assert_eq!(KeyboardLayout::by_name("UK").unwrap().name(), "gb");
+        assert_eq!(KeyboardLayout::by_name("fi").unwrap().name(), "se");
+        assert!(KeyboardLayout::by_name("xx").is_none());
+    }
+
+    #[test]
+    fn us() {
+        let us = KeyboardLayout::us();
+        assert_eq!(stroke(&us, 'a'), (0x04, 0));
+        assert_eq!(stroke(&us, 'A'), (0x04, SHIFT));
+        assert_eq!(stroke(&us, '@'), (0x1F, SHIFT));
+        assert_eq!(stroke(&us, '\n'), (0x28, 0));
+        assert!(us.get('ä').is_none());
+    }
+
+    #[test]
+    fn other_hosts() {
+        let de = KeyboardLayout::de();
+        assert_eq!(stroke(&de, 'z'), (0x1C, 0));
+        assert_eq!(stroke(&de, 'y'), (0x1D, 0));
+        assert_eq!(stroke(&de, '@'), (0x14, ALTGR));
+        assert_eq!(stroke(&KeyboardLayout::fr(), 'a'), (0x14, 0));
+        assert_eq!(stroke(&KeyboardLayout::gb(), '"'), (0x1F, SHIFT));
+    }
+
+    #[test]
+    fn dead_keys() {
+        let de = KeyboardLayout::de();
+        let mapping = de.get('é').unwrap();
+        assert_eq!(mapping.stroke, KeyStroke::new(0x08, 0));
+        assert!(mapping.dead_key.is_some());
+        // The accent alone is the dead key followed by space
+        assert_eq!(de.get('´').unwrap().stroke, KeyStroke::new(SPACE, 0));
+    }
+
+    #[test]
+    fn shift_and_altgr_are_pressed_first() {
+        let reports = KeyboardLayout::us().text_to_reports("aA").unwrap();
+        let expected = [
+            [0, 0, 0x04, 0, 0, 0, 0, 0],
+            [0; 8],
+            [SHIFT, 0, 0, 0, 0, 0, 0, 0],
+            [SHIFT, 0, 0x04, 0, 0, 0, 0, 0],
+            [0; 8],
+        ];
+        assert_eq!(reports, expected);
+        let reports = KeyboardLayout::de().text_to_reports("@").unwrap();
+        let expected = [
+            [ALTGR, 0, 0, 0, 0, 0, 0, 0],
+            [ALTGR, 0, 0x14, 0, 0, 0, 0, 0],
+            [0; 8],
+        ];
+        assert_eq!(reports, expected);
+    }
+
+    #[test]
+    fn accents_type_the_dead_key_first() {
+        let reports = KeyboardLayout::de().text_to_reports("é").unwrap();
+        let expected = [
+            [0, 0, 0x2E, 0, 0, 0, 0, 0],
+            [0; 8],
+            [0, 0, 0x08, 0, 0, 0, 0, 0],
+            [0; 8],
+        ];
+        assert_eq!(reports, expected);
+    }
+
+    #[test]
+    fn untypeable_text() {
+        let result = KeyboardLayout::us().text_to_actions("naïve");
+        assert!(matches!(result, Err(WMSError::LayoutError(message)) if message.contains('ï')));
+    }
+}
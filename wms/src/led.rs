@@ -0,0 +1,223 @@
+use std::io::Read;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use crate::{Key, KillSwitch, WMSError};
+
+/// Lock LEDs of a keyboard, as sent by the host in output reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LedState(u8);
+
+impl LedState {
+    pub const NONE: LedState = LedState(0);
+    pub const NUM_LOCK: LedState = LedState(0x01);
+    pub const CAPS_LOCK: LedState = LedState(0x02);
+    pub const SCROLL_LOCK: LedState = LedState(0x04);
+    pub const COMPOSE: LedState = LedState(0x08);
+    pub const KANA: LedState = LedState(0x10);
+
+    pub fn from_bits(bits: u8) -> LedState {
+        LedState(bits)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns whether every LED in `leds` is on
+    pub fn contains(self, leds: LedState) -> bool {
+        self.0 & leds.0 == leds.0
+    }
+
+    pub fn num_lock(self) -> bool {
+        self.contains(LedState::NUM_LOCK)
+    }
+
+    pub fn caps_lock(self) -> bool {
+        self.contains(LedState::CAPS_LOCK)
+    }
+
+    pub fn scroll_lock(self) -> bool {
+        self.contains(LedState::SCROLL_LOCK)
+    }
+}
+
+/// How often a wait on the host checks whether it was aborted
+const ABORT_POLL: Duration = Duration::from_millis(50);
+
+/// Lock keys that toggle each LED
+pub(crate) const LOCK_KEYS: [(LedState, Key); 3] = [
+    (LedState::NUM_LOCK, Key::NumLock),
+    (LedState::CAPS_LOCK, Key::CapsLock),
+    (LedState::SCROLL_LOCK, Key::ScrollLock),
+];
+
+/// What a script waits for the host to do with its lock LEDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedWait {
+    On,
+    Off,
+    Change,
+}
+
+/// Tracks the LED state the host last reported
+///
+/// Clones share the same state, so one can be kept while the other is read from
+/// the gadget.
+#[derive(Debug, Clone, Default)]
+pub struct LedMonitor {
+    shared: Arc<(Mutex<Leds>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct Leds {
+    state: LedState,
+    subscribers: Vec<Sender<LedState>>,
+}
+
+impl LedMonitor {
+    pub fn new() -> LedMonitor {
+        LedMonitor::default()
+    }
+
+    /// Reads output reports on a new thread, e.g. from the gadget's `/dev/hidgN`
+    pub fn spawn(mut reader: impl Read + Send + 'static) -> LedMonitor {
+        let monitor = LedMonitor::new();
+        let updater = monitor.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 8];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => updater.update(LedState(buf[0])),
+                    Err(e) => {
+                        eprintln!("Error reading LED report: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        monitor
+    }
+
+    /// Records a state reported by the host
+    pub fn update(&self, state: LedState) {
+        let (leds, changed) = &*self.shared;
+        let mut leds = leds.lock().unwrap();
+        if leds.state == state {
+            return;
+        }
+        println!("Host LEDs {:#04x}", state.0);
+        leds.state = state;
+        leds.subscribers.retain(|s| s.send(state).is_ok());
+        changed.notify_all();
+    }
+
+    /// Returns the LED state the host last reported
+    pub fn state(&self) -> LedState {
+        self.shared.0.lock().unwrap().state
+    }
+
+    /// Returns a receiver for every LED state the host reports from now on
+    pub fn changes(&self) -> Receiver<LedState> {
+        let (sender, receiver) = channel();
+        self.shared.0.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    /// Blocks until the LEDs in `leds` are all on, all off, or one of them changes
+    pub fn wait(&self, leds: LedState, wait: LedWait) -> LedState {
+        let (lock, changed) = &*self.shared;
+        let guard = lock.lock().unwrap();
+        let done = wait_done(guard.state, leds, wait);
+        changed.wait_while(guard, |l| !done(l.state)).unwrap().state
+    }
+
+    /// Like [`LedMonitor::wait`], giving up and returning `None` after `timeout`
+    pub fn wait_timeout(
+        &self,
+        leds: LedState,
+        wait: LedWait,
+        timeout: Duration,
+    ) -> Option<LedState> {
+        let (lock, changed) = &*self.shared;
+        let guard = lock.lock().unwrap();
+        let done = wait_done(guard.state, leds, wait);
+        let (guard, result) = changed
+            .wait_timeout_while(guard, timeout, |l| !done(l.state))
+            .unwrap();
+        (!result.timed_out()).then_some(guard.state)
+    }
+
+    /// Like [`LedMonitor::wait`], giving up if `switch` aborts
+    pub(crate) fn wait_unless_aborted(
+        &self,
+        leds: LedState,
+        wait: LedWait,
+        switch: &KillSwitch,
+    ) -> Result<LedState, WMSError> {
+        let (lock, changed) = &*self.shared;
+        let mut guard = lock.lock().unwrap();
+        let done = wait_done(guard.state, leds, wait);
+        while !done(guard.state) {
+            switch.check()?;
+            guard = changed.wait_timeout(guard, ABORT_POLL).unwrap().0;
+        }
+        Ok(guard.state)
+    }
+}
+
+/// Returns whether a state ends a wait that started at `start`
+fn wait_done(start: LedState, leds: LedState, wait: LedWait) -> impl Fn(LedState) -> bool {
+    move |state| match wait {
+        LedWait::On => state.0 & leds.0 == leds.0,
+        LedWait::Off => state.0 & leds.0 == 0,
+        LedWait::Change => (state.0 ^ start.0) & leds.0 != 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_output_reports() {
+        let reports: &[u8] = &[0x02, 0, 0, 0, 0, 0, 0, 0];
+        let monitor = LedMonitor::spawn(reports);
+        let state = monitor.wait_timeout(LedState::CAPS_LOCK, LedWait::On, Duration::from_secs(1));
+        assert_eq!(state, Some(LedState::CAPS_LOCK));
+        assert!(monitor.state().caps_lock() && !monitor.state().num_lock());
+    }
+
+    #[test]
+    fn waits() {
+        let caps_and_num = LedState::from_bits(0x03);
+        let on = wait_done(LedState::default(), caps_and_num, LedWait::On);
+        assert!(!on(LedState::CAPS_LOCK) && on(LedState::from_bits(0x07)));
+        let off = wait_done(LedState::default(), LedState::CAPS_LOCK, LedWait::Off);
+        assert!(off(LedState::NUM_LOCK) && !off(LedState::CAPS_LOCK));
+        let change = wait_done(LedState::NUM_LOCK, LedState::NUM_LOCK, LedWait::Change);
+        assert!(!change(LedState::from_bits(0x05)) && change(LedState::default()));
+    }
+
+    #[test]
+    fn wait_wakes_on_update() {
+        let monitor = LedMonitor::new();
+        let changes = monitor.changes();
+        let host = monitor.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            host.update(LedState::SCROLL_LOCK);
+        });
+        let state = monitor.wait(LedState::SCROLL_LOCK, LedWait::Change);
+        assert!(state.scroll_lock());
+        assert_eq!(changes.recv().unwrap(), LedState::SCROLL_LOCK);
+        thread.join().unwrap();
+        let timeout = Duration::from_millis(10);
+        assert_eq!(
+            monitor.wait_timeout(LedState::NUM_LOCK, LedWait::On, timeout),
+            None
+        );
+    }
+}
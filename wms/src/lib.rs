@@ -1,43 +1,192 @@
 use std::io::Write;
 use usb_gadget::{
-    default_udc,
     function::{
         hid::Hid,
         msd::{Lun, Msd},
+        Handle,
     },
-    Class, Config, Gadget, Id, RegGadget, Strings,
+    RegGadget,
 };
 
 use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
 
 use rusb::{Context, Device, DeviceHandle, HotplugBuilder, InterfaceDescriptor, UsbContext};
 
+mod audit;
+mod bundle;
+mod control;
+#[macro_use]
+mod device;
+mod gadget;
+mod inject;
+mod keys;
+mod kill;
+mod layout;
+mod led;
+mod mouse;
+mod nkro;
+mod profile;
+mod rawhid;
+mod scope;
+mod script;
+mod sink;
+mod timeline;
+mod timing;
+mod xkb;
+
+use audit::Audited;
+pub use audit::{verify_audit_log, AuditLog, AuditSummary};
+pub use bundle::{sign_bundle, Bundle, BundleFile, Manifest};
+pub use control::{Control, CONTROL_REPORT_DESC};
+use device::DeviceSetup;
+pub use gadget::{
+    hidg_path, udc_state, unbind, wait_for_host, BoundGadget, Composite, DEVICE_TIMEOUT,
+    ENUMERATION_TIMEOUT,
+};
+use inject::InjectOptions;
+pub use keys::{Chord, Key};
+pub use kill::KillSwitch;
+pub use layout::{KeyStroke, KeyboardLayout, Mapping};
+pub use led::{LedMonitor, LedState, LedWait};
+pub use mouse::{
+    parse_mouse_script, MouseAction, MouseButton, MouseState, ABSOLUTE_MAX, MOUSE_REPORT_DESC,
+};
+pub use nkro::{nkro_report, HidProtocol, NKRO_REPORT_DESC};
+pub use profile::GadgetProfile;
+pub use rawhid::{parse_hex, parse_raw_script, read_report_desc, RawAction};
+pub use scope::{read_team_key, Authorization, Scope};
+use script::parse_chord;
+pub use script::{
+    parse_nkro_script, parse_script, parse_script_with_layout, Action, KeyState, ScriptError,
+    BOOT_ROLLOVER,
+};
+pub use sink::{FileCapture, HidgSink, Recorder, ReportSink};
+pub use timeline::{Timeline, TimelineEntry};
+pub use timing::Timing;
+pub use xkb::XKB_SYMBOLS_DIR;
+
 const HID: u8 = 0x03;
 const HID_KEYBOARD: u8 = 0x01;
 
 #[derive(Debug)]
 pub enum WMSError {
     FileError(std::io::Error),
-    SyntaxError,
+    SyntaxError(Vec<ScriptError>),
     GadgetSetupError(std::io::Error),
+    LayoutError(String),
+    ProfileError(String),
+    /// A timing with a speed multiplier that isn't positive and finite
+    TimingError(String),
+    /// The scope file could not be read
+    ScopeMissing(std::io::Error),
+    /// The scope file is malformed or lacks a required key
+    ScopeError(String),
+    /// The engagement ended on the given date
+    ScopeExpired(String),
+    /// The operator is not the one the scope allows
+    OperatorNotAllowed(String),
+    /// The scope carries no signature
+    ScopeUnsigned,
+    /// The scope's signature does not match the team key
+    BadScopeSignature,
+    /// An attack was started without an [`Authorization`]
+    NotAuthorized,
+    /// The audit log was modified or cut short
+    AuditError(String),
+    /// A [`KillSwitch`] stopped the attack
+    Aborted,
+    /// The bundle is malformed or one of its files can't be read
+    BundleError(String),
+    /// The bundle has no signature
+    BundleUnsigned,
+    /// The bundle's manifest was not signed with the team key
+    BadBundleSignature,
+    /// A file does not match the hash in the signed manifest
+    BundleTampered(String),
     RuntimeError,
 }
 
 impl std::fmt::Display for WMSError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", *self)
+        match self {
+            WMSError::SyntaxError(errors) => {
+                write!(f, "{} error(s) in script", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+            _ => write!(f, "{:?}", *self),
+        }
     }
 }
 
 impl std::error::Error for WMSError {}
 
 pub trait Attack {
-    fn setup_gadget(&mut self) -> Result<RegGadget, WMSError>;
+    fn setup_gadget(&mut self) -> Result<BoundGadget, WMSError>;
+
+    /// Log the device records its actions in, if any
+    fn audit_log(&self) -> Option<&AuditLog> {
+        None
+    }
+}
+
+/// A USB function provided by a device, see [`Composite`] for gadgets with several
+pub trait GadgetFunction {
+    /// Builds the functions to add to a gadget configuration
+    fn build_functions(&mut self) -> Result<Vec<Handle>, WMSError>;
+    /// Finishes setting up once the gadget has been bound to a UDC
+    fn bound(&mut self, gadget: &BoundGadget) -> Result<(), WMSError>;
 }
 
 pub trait InputAttack: Attack {
-    fn read_script(&mut self, path: &str) -> Result<(), WMSError>;
+    /// Parses a script into the actions [`InputAttack::input_attack`] sends
+    ///
+    /// Only built with the `dev-bundles` feature, so field devices only run
+    /// reviewed payloads loaded with the devices' `load_bundle`.
+    #[cfg(feature = "dev-bundles")]
+    fn load_script(&mut self, script: &str) -> Result<(), WMSError>;
+
+    /// Sends the loaded script to the host
+    ///
+    /// When a [`KillSwitch`] aborts it, everything held is released, the
+    /// gadget the device was bound in is unbound and [`WMSError::Aborted`]
+    /// returned.
     fn input_attack(&mut self) -> Result<(), WMSError>;
+
+    /// Reads a script file, see [`InputAttack::load_script`]
+    #[cfg(feature = "dev-bundles")]
+    fn read_script(&mut self, path: &str) -> Result<(), WMSError> {
+        let script = std::fs::read_to_string(path).map_err(WMSError::FileError)?;
+        println!("Read {}", script);
+        if let Some(log) = self.audit_log() {
+            log.record_script(path, &script)?;
+        }
+        self.load_script(&script)
+    }
+
+    /// Waits for the host to configure the gadget, then starts the payload
+    ///
+    /// If a [`KillSwitch`] aborts the payload, the gadget is unbound, even if
+    /// the payload sent nothing through a device bound in it.
+    fn on_host_connected<F>(
+        &mut self,
+        reg: &RegGadget,
+        timeout: std::time::Duration,
+        payload: F,
+    ) -> Result<(), WMSError>
+    where
+        Self: Sized,
+        F: FnOnce(&mut Self) -> Result<(), WMSError>,
+    {
+        wait_for_host(reg, timeout)?;
+        let result = payload(self);
+        if let Err(WMSError::Aborted) = result {
+            unbind(reg, self.audit_log())?;
+        }
+        result
+    }
 }
 
 pub trait SnoopAttack: Attack {
@@ -46,101 +195,264 @@ pub trait SnoopAttack: Attack {
 }
 
 pub struct WMSKeyboardDevice {
-    keystrokes: Vec<[u8; 8]>,
+    actions: Vec<Action>,
+    layout: KeyboardLayout,
+    timing: Timing,
     file: Option<std::fs::File>,
-    keyboard_fd: Option<std::fs::File>,
+    sink: Option<Box<dyn ReportSink>>,
+    leds: Option<LedMonitor>,
+    compensate_locks: bool,
+    hid: Option<Hid>,
+    control_sink: Option<Box<dyn ReportSink>>,
+    control_hid: Option<Hid>,
+    nkro: bool,
+    protocol: HidProtocol,
+    setup: DeviceSetup,
 }
 
 impl WMSKeyboardDevice {
     pub fn new() -> WMSKeyboardDevice {
         WMSKeyboardDevice {
-            keystrokes: Vec::new(),
+            actions: Vec::new(),
+            layout: KeyboardLayout::us(),
+            timing: Timing::default(),
             file: None,
-            keyboard_fd: None,
+            sink: None,
+            leds: None,
+            compensate_locks: true,
+            hid: None,
+            control_sink: None,
+            control_hid: None,
+            nkro: false,
+            protocol: HidProtocol::Report,
+            setup: DeviceSetup::default(),
         }
     }
+}
+
+impl Default for WMSKeyboardDevice {
+    fn default() -> WMSKeyboardDevice {
+        WMSKeyboardDevice::new()
+    }
+}
 
+impl WMSKeyboardDevice {
     /// Converts a string to a HID report
     ///
-    /// Strings represent a single character, potentially with modifiers
+    /// Strings represent up to 6 keys, potentially with modifiers
     /// Lines may look as follows:
     /// Single character: "a"
     /// Character with modifier: "shift a"
     /// Character with multiple modifiers: "shift ctrl a"
-    pub fn string_to_report(s: &str) -> [u8; 8] {
-        str_to_keycode(s)
+    /// Several keys at once: "ctrl a s d"
+    ///
+    /// Unknown keys and more than six keys fail with [`WMSError::SyntaxError`].
+    pub fn string_to_report(s: &str) -> Result<[u8; 8], WMSError> {
+        Ok(parse_chord(s, &KeyboardLayout::us())?.boot_report())
+    }
+
+    /// Sets the keyboard layout of the target host, used when scripts are read
+    pub fn set_layout(&mut self, layout: KeyboardLayout) {
+        self.layout = layout;
+    }
+
+    pub fn layout(&self) -> &KeyboardLayout {
+        &self.layout
+    }
+
+    /// Sets how long keys are held, the wait between them and the speed multiplier
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Sends reports to `sink` instead of the gadget's HID device
+    ///
+    /// [`Attack::setup_gadget`] replaces the sink with the gadget's HID device.
+    pub fn set_sink(&mut self, sink: impl ReportSink + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Sends media and power key reports to `sink` instead of the gadget's control device
+    pub fn set_control_sink(&mut self, sink: impl ReportSink + 'static) {
+        self.control_sink = Some(Box::new(sink));
+    }
+
+    /// Tracks the host's lock LEDs with `leds` instead of the gadget's HID device
+    pub fn set_led_monitor(&mut self, leds: LedMonitor) {
+        self.leds = Some(leds);
+    }
+
+    /// Returns the lock LEDs the host last reported, once the gadget is set up
+    pub fn led_state(&self) -> Option<LedState> {
+        self.leds.as_ref().map(LedMonitor::state)
+    }
+
+    /// Returns a receiver for every change of the host's lock LEDs
+    pub fn led_changes(&self) -> Option<std::sync::mpsc::Receiver<LedState>> {
+        self.leds.as_ref().map(LedMonitor::changes)
+    }
+
+    /// Sets whether CapsLock on the host is turned off while text is typed
+    ///
+    /// This is on by default, CapsLock is turned back on after each piece of text.
+    /// NumLock is left alone, as it only changes keypad keys which text isn't typed with.
+    pub fn set_lock_compensation(&mut self, compensate: bool) {
+        self.compensate_locks = compensate;
+    }
+
+    /// Loads the script of a [`Bundle`], typed with the layout its manifest names
+    ///
+    /// Nothing changes unless the bundle uses the `keyboard` function and its
+    /// script parses.
+    pub fn load_bundle(&mut self, bundle: &Bundle) -> Result<(), WMSError> {
+        let script = bundle::checked_script(bundle, "keyboard", self.setup.audit.as_ref())?;
+        let layout = bundle.layout()?;
+        self.actions = self.parse(script, &layout)?;
+        self.layout = layout;
+        Ok(())
+    }
+
+    /// Parses a DuckyScript typed with `layout`, allowing more than six keys with NKRO
+    fn parse(&self, script: &str, layout: &KeyboardLayout) -> Result<Vec<Action>, WMSError> {
+        match self.nkro {
+            true => parse_nkro_script(script, layout),
+            false => parse_script_with_layout(script, layout),
+        }
+    }
+
+    /// Works out the reports the loaded script sends and when, without a UDC
+    pub fn compile(&self) -> Timeline {
+        let nkro = self.nkro && self.protocol == HidProtocol::Report;
+        Timeline::compile(&self.actions, &self.timing, nkro)
+    }
+
+    /// Uses an N-key rollover report descriptor instead of the boot keyboard one
+    ///
+    /// Takes effect at the next [`Attack::setup_gadget`], and lets scripts loaded
+    /// afterwards hold any number of keys at once, see [`parse_nkro_script`].
+    pub fn set_nkro(&mut self, nkro: bool) {
+        self.nkro = nkro;
+    }
+
+    /// Sets the protocol the host selected, which decides the format of the reports sent
+    ///
+    /// Hosts can ask a boot keyboard for 8-byte boot reports with SET_PROTOCOL,
+    /// which the kernel's HID gadget answers without telling user space, so the
+    /// device can't follow the host on its own. NKRO reports begin with a boot
+    /// report, which is enough for boot hosts that read the first 8 bytes, but
+    /// hosts that reject longer reports, such as some BIOSes, only work once
+    /// this is set to [`HidProtocol::Boot`]. Only NKRO keyboards are affected,
+    /// as boot keyboards use that format for both.
+    pub fn set_host_protocol(&mut self, protocol: HidProtocol) {
+        self.protocol = protocol;
+    }
+
+    /// Types text on the host, pressing shift or AltGr where the layout needs it
+    ///
+    /// The gadget must have been set up with [`Attack::setup_gadget`], or a sink
+    /// given with [`WMSKeyboardDevice::set_sink`], first.
+    pub fn type_text(&mut self, text: &str) -> Result<(), WMSError> {
+        let mut actions = vec![Action::BeginText];
+        actions.extend(self.layout.text_to_actions(text)?);
+        actions.push(Action::EndText);
+        self.send(&actions)
+    }
+
+    fn send(&mut self, actions: &[Action]) -> Result<(), WMSError> {
+        scope::authorized(self.setup.authorization.as_ref())?;
+        let log = self.setup.audit.as_ref();
+        let sink = self.sink.as_deref_mut().ok_or(WMSError::RuntimeError)?;
+        let mut sink = Audited::new(sink, log, "keyboard");
+        let mut controls = (self.control_sink.as_deref_mut())
+            .map(|controls| Audited::new(controls, log, "control"));
+        let result = inject::send_actions(
+            &mut sink,
+            controls
+                .as_mut()
+                .map(|controls| controls as &mut dyn ReportSink),
+            actions,
+            InjectOptions {
+                timing: &self.timing,
+                leds: self.leds.as_ref(),
+                compensate: self.compensate_locks,
+                nkro: self.nkro && self.protocol == HidProtocol::Report,
+                switch: &self.setup.kill_switch,
+            },
+        );
+        self.setup.end(result)
     }
 }
 
-impl Attack for WMSKeyboardDevice {
-    fn setup_gadget(&mut self) -> Result<RegGadget, WMSError> {
+impl GadgetFunction for WMSKeyboardDevice {
+    fn build_functions(&mut self) -> Result<Vec<Handle>, WMSError> {
         let mut builder = Hid::builder();
         builder.protocol = 1;
         builder.sub_class = 1;
-        builder.report_len = 8;
-        builder.report_desc = KeyboardReport::desc().to_vec(); //std::fs::read("~/kybd-descriptor.bin").expect("Could not open file: kybd-descriptor.bin");
+        if self.nkro {
+            builder.report_len = nkro::NKRO_REPORT_LEN;
+            builder.report_desc = NKRO_REPORT_DESC.to_vec();
+        } else {
+            builder.report_len = 8;
+            builder.report_desc = KeyboardReport::desc().to_vec(); //std::fs::read("~/kybd-descriptor.bin").expect("Could not open file: kybd-descriptor.bin");
+        }
         let (hid, handle) = builder.build();
+        self.hid = Some(hid);
 
-        let udc = default_udc().expect("Cannot get UDC");
-        let reg = Gadget::new(
-            Class::new(1, 2, 3),
-            Id::new(4, 5),
-            Strings::new("Cole", "evil USB", "Cereal Value"),
-        )
-        .with_config(Config::new("cfg1").with_function(handle))
-        .bind(&udc)
-        .map_err(|e| WMSError::GadgetSetupError(e))?;
+        // Boot keyboards can't have report IDs, so media and power keys get their own function
+        let mut builder = Hid::builder();
+        builder.report_len = control::CONTROL_REPORT_LEN;
+        builder.report_desc = CONTROL_REPORT_DESC.to_vec();
+        let (control_hid, control_handle) = builder.build();
+        self.control_hid = Some(control_hid);
+        Ok(vec![handle, control_handle])
+    }
 
+    fn bound(&mut self, gadget: &BoundGadget) -> Result<(), WMSError> {
+        self.setup.gadget = gadget.link();
+        let hid = self.hid.as_ref().ok_or(WMSError::RuntimeError)?;
         println!(
             "HID device {:?} at {}",
             hid.device().unwrap(),
             hid.status().path().unwrap().display()
         );
-        let kybd_fd = std::fs::File::options()
-            .read(false)
-            .write(true)
-            .open("/dev/hidg0") // TODO: Can this be acquired programmatically?
-            .expect("Could not open HID device");
-
-        std::thread::sleep(std::time::Duration::from_millis(1000));
-        self.keyboard_fd = Some(kybd_fd);
-        Ok(reg)
+        let path = hidg_path(hid, DEVICE_TIMEOUT)?;
+        let sink = HidgSink::open(&path).map_err(WMSError::GadgetSetupError)?;
+        let output = std::fs::File::open(&path).map_err(WMSError::GadgetSetupError)?;
+        self.sink = Some(Box::new(sink));
+        self.leds = Some(LedMonitor::spawn(output));
+
+        let control_hid = self.control_hid.as_ref().ok_or(WMSError::RuntimeError)?;
+        let path = hidg_path(control_hid, DEVICE_TIMEOUT)?;
+        println!("Control device at {}", path.display());
+        let sink = HidgSink::open(&path).map_err(WMSError::GadgetSetupError)?;
+        self.control_sink = Some(Box::new(sink));
+        Ok(())
     }
 }
 
+device_setup!(
+    WMSKeyboardDevice,
+    "Records gadget setup, scripts loaded and every report sent in `log`",
+    kill_switch
+);
+
 impl InputAttack for WMSKeyboardDevice {
-    fn read_script(&mut self, path: &str) -> Result<(), WMSError> {
-        let script = std::fs::read_to_string(path).expect("Could not read script file");
-        println!("Read {}", script);
-        let lines = script.lines();
-        for line in lines {
-            self.keystrokes.push(str_to_keycode(line))
-        }
+    /// Parses a DuckyScript, see [`parse_script_with_layout`] for the supported commands
+    #[cfg(feature = "dev-bundles")]
+    fn load_script(&mut self, script: &str) -> Result<(), WMSError> {
+        self.actions = self.parse(script, &self.layout)?;
         Ok(())
     }
 
     fn input_attack(&mut self) -> Result<(), WMSError> {
-        let mut kybd_fd = self.keyboard_fd.take().unwrap();
-        for keystroke in &self.keystrokes {
-            kybd_fd
-                .write_all(keystroke)
-                .map_err(|e| WMSError::FileError(e))?;
-            //todo!("Convert keystroke input to HID report");
-            // let mut keyboard_report: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
-            // let mut i = 2;
-            // for c in keystroke.chars() {
-            //     keyboard_report[i] = c as u8;
-            //     i += 1;
-            // }
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            //let rv = kybd_fd.write_all(&keyboard_report);
-            //println!("Wrote {:?} bytes\n", rv);
-        }
-        kybd_fd
-            .write_all(&[0u8; 8])
-            .expect("Could not write to HID");
-
-        Ok(())
+        let actions = std::mem::take(&mut self.actions);
+        let result = self.send(&actions);
+        self.actions = actions;
+        result
     }
 }
 
@@ -176,7 +488,7 @@ impl<T: UsbContext> rusb::Hotplug<T> for WMSKeyboardDevice {
                 claim_interface(&mut handle, idesc.interface_number());
 
                 let mut buf: Vec<u8> = vec![0u8; endpdesc.max_packet_size().into()];
-                let mut kybd_fd = self.keyboard_fd.take().unwrap();
+                let mut sink = self.sink.take().unwrap();
                 let mut log_fd = self.file.take().unwrap();
 
                 loop {
@@ -187,7 +499,7 @@ impl<T: UsbContext> rusb::Hotplug<T> for WMSKeyboardDevice {
                     ) {
                         Ok(_) => {
                             println!("Read {:?} bytes", buf);
-                            kybd_fd.write_all(&buf).expect("Could not write to HID");
+                            sink.send_report(&buf).expect("Could not write to HID");
                             log_fd.write_all(&buf).expect("Could not write to log");
                         }
                         Err(e) => {
@@ -210,13 +522,16 @@ impl SnoopAttack for WMSKeyboardDevice {
             std::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(path)
-                .map_err(|e| WMSError::FileError(e))?,
+                .map_err(WMSError::FileError)?,
         );
         Ok(())
     }
 
     fn snoop_attack(self) -> Result<(), WMSError> {
+        scope::authorized(self.setup.authorization.as_ref())?;
+
         // Step 1: Wait for keyboard to be plugged in
         if !rusb::has_hotplug() {
             println!("Hotplug not supported!");
@@ -238,9 +553,219 @@ impl SnoopAttack for WMSKeyboardDevice {
     }
 }
 
+pub struct WMSMouseDevice {
+    actions: Vec<MouseAction>,
+    timing: Timing,
+    sink: Option<Box<dyn ReportSink>>,
+    hid: Option<Hid>,
+    setup: DeviceSetup,
+}
+
+impl WMSMouseDevice {
+    pub fn new() -> WMSMouseDevice {
+        WMSMouseDevice {
+            actions: Vec::new(),
+            timing: Timing::new(
+                std::time::Duration::from_millis(50),
+                std::time::Duration::from_millis(10),
+            ),
+            sink: None,
+            hid: None,
+            setup: DeviceSetup::default(),
+        }
+    }
+}
+
+impl Default for WMSMouseDevice {
+    fn default() -> WMSMouseDevice {
+        WMSMouseDevice::new()
+    }
+}
+
+impl WMSMouseDevice {
+    /// Sets how long buttons are held and the wait after every other report
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// Sends reports to `sink` instead of the gadget's HID device
+    pub fn set_sink(&mut self, sink: impl ReportSink + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Loads the mouse script of a [`Bundle`] using the `mouse` function
+    pub fn load_bundle(&mut self, bundle: &Bundle) -> Result<(), WMSError> {
+        let script = bundle::checked_script(bundle, "mouse", self.setup.audit.as_ref())?;
+        self.actions = parse_mouse_script(script)?;
+        Ok(())
+    }
+
+    /// Moves, clicks or scrolls on the host straight away
+    pub fn send(&mut self, actions: &[MouseAction]) -> Result<(), WMSError> {
+        scope::authorized(self.setup.authorization.as_ref())?;
+        let log = self.setup.audit.as_ref();
+        let sink = self.sink.as_deref_mut().ok_or(WMSError::RuntimeError)?;
+        let mut sink = Audited::new(sink, log, "mouse");
+        let result =
+            inject::send_mouse_actions(&mut sink, actions, &self.timing, &self.setup.kill_switch);
+        self.setup.end(result)
+    }
+}
+
+impl GadgetFunction for WMSMouseDevice {
+    fn build_functions(&mut self) -> Result<Vec<Handle>, WMSError> {
+        let mut builder = Hid::builder();
+        builder.protocol = 0;
+        builder.sub_class = 0;
+        builder.report_len = mouse::MOUSE_REPORT_LEN;
+        builder.report_desc = MOUSE_REPORT_DESC.to_vec();
+        let (hid, handle) = builder.build();
+        self.hid = Some(hid);
+        Ok(vec![handle])
+    }
+
+    fn bound(&mut self, gadget: &BoundGadget) -> Result<(), WMSError> {
+        self.setup.gadget = gadget.link();
+        let hid = self.hid.as_ref().ok_or(WMSError::RuntimeError)?;
+        let path = hidg_path(hid, DEVICE_TIMEOUT)?;
+        println!("Mouse device at {}", path.display());
+        let sink = HidgSink::open(&path).map_err(WMSError::GadgetSetupError)?;
+        self.sink = Some(Box::new(sink));
+        Ok(())
+    }
+}
+
+device_setup!(
+    WMSMouseDevice,
+    "Records gadget setup, scripts loaded and every report sent in `log`",
+    kill_switch
+);
+
+impl InputAttack for WMSMouseDevice {
+    /// Parses a mouse script, see [`parse_mouse_script`] for the supported commands
+    #[cfg(feature = "dev-bundles")]
+    fn load_script(&mut self, script: &str) -> Result<(), WMSError> {
+        self.actions = parse_mouse_script(script)?;
+        Ok(())
+    }
+
+    fn input_attack(&mut self) -> Result<(), WMSError> {
+        let actions = std::mem::take(&mut self.actions);
+        let result = self.send(&actions);
+        self.actions = actions;
+        result
+    }
+}
+
+/// A HID device with any report descriptor, such as a game controller or barcode scanner
+pub struct WMSRawHidDevice {
+    report_desc: Vec<u8>,
+    report_len: u8,
+    actions: Vec<RawAction>,
+    timing: Timing,
+    sink: Option<Box<dyn ReportSink>>,
+    hid: Option<Hid>,
+    setup: DeviceSetup,
+}
+
+impl WMSRawHidDevice {
+    /// Creates a device with a report descriptor and the length of its longest report
+    pub fn new(report_desc: Vec<u8>, report_len: u8) -> WMSRawHidDevice {
+        WMSRawHidDevice {
+            report_desc,
+            report_len,
+            actions: Vec::new(),
+            timing: Timing::new(
+                std::time::Duration::ZERO,
+                std::time::Duration::from_millis(10),
+            ),
+            sink: None,
+            hid: None,
+            setup: DeviceSetup::default(),
+        }
+    }
+
+    /// Creates a device with a report descriptor read by [`read_report_desc`]
+    pub fn from_desc_file(
+        path: impl AsRef<std::path::Path>,
+        report_len: u8,
+    ) -> Result<WMSRawHidDevice, WMSError> {
+        Ok(WMSRawHidDevice::new(read_report_desc(path)?, report_len))
+    }
+
+    /// Sets the wait after each report, the gap of `timing`
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// Sends reports to `sink` instead of the gadget's HID device
+    pub fn set_sink(&mut self, sink: impl ReportSink + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Loads the script of hex reports of a [`Bundle`] using the `raw_hid` function
+    pub fn load_bundle(&mut self, bundle: &Bundle) -> Result<(), WMSError> {
+        let script = bundle::checked_script(bundle, "raw_hid", self.setup.audit.as_ref())?;
+        self.actions = parse_raw_script(script, self.report_len)?;
+        Ok(())
+    }
+}
+
+impl GadgetFunction for WMSRawHidDevice {
+    fn build_functions(&mut self) -> Result<Vec<Handle>, WMSError> {
+        let mut builder = Hid::builder();
+        builder.report_len = self.report_len;
+        builder.report_desc = self.report_desc.clone();
+        let (hid, handle) = builder.build();
+        self.hid = Some(hid);
+        Ok(vec![handle])
+    }
+
+    fn bound(&mut self, gadget: &BoundGadget) -> Result<(), WMSError> {
+        self.setup.gadget = gadget.link();
+        let hid = self.hid.as_ref().ok_or(WMSError::RuntimeError)?;
+        let path = hidg_path(hid, DEVICE_TIMEOUT)?;
+        println!("Raw HID device at {}", path.display());
+        let sink = HidgSink::open(&path).map_err(WMSError::GadgetSetupError)?;
+        self.sink = Some(Box::new(sink));
+        Ok(())
+    }
+}
+
+device_setup!(
+    WMSRawHidDevice,
+    "Records gadget setup, scripts loaded and every report sent in `log`",
+    kill_switch
+);
+
+impl InputAttack for WMSRawHidDevice {
+    /// Parses a script of hex reports, see [`parse_raw_script`] for the format
+    #[cfg(feature = "dev-bundles")]
+    fn load_script(&mut self, script: &str) -> Result<(), WMSError> {
+        self.actions = parse_raw_script(script, self.report_len)?;
+        Ok(())
+    }
+
+    fn input_attack(&mut self) -> Result<(), WMSError> {
+        scope::authorized(self.setup.authorization.as_ref())?;
+        let log = self.setup.audit.as_ref();
+        let sink = self.sink.as_deref_mut().ok_or(WMSError::RuntimeError)?;
+        let mut sink = Audited::new(sink, log, "raw");
+        let result = inject::send_raw_actions(
+            &mut sink,
+            &self.actions,
+            &self.timing,
+            &self.setup.kill_switch,
+        );
+        self.setup.end(result)
+    }
+}
+
 pub struct WMSMassStorageDevice {
     fakefs: std::path::PathBuf,
     logfs: Option<std::path::PathBuf>,
+    msd: Option<Msd>,
+    setup: DeviceSetup,
 }
 
 impl WMSMassStorageDevice {
@@ -248,31 +773,33 @@ impl WMSMassStorageDevice {
         Ok(WMSMassStorageDevice {
             fakefs: std::fs::canonicalize(path)?,
             logfs: None,
+            msd: None,
+            setup: DeviceSetup::default(),
         })
     }
 }
 
-impl Attack for WMSMassStorageDevice {
-    fn setup_gadget(&mut self) -> Result<RegGadget, WMSError> {
+impl GadgetFunction for WMSMassStorageDevice {
+    fn build_functions(&mut self) -> Result<Vec<Handle>, WMSError> {
         let mut builder = Msd::builder();
-        builder.add_lun(Lun::new(self.fakefs.clone()).map_err(|e| WMSError::FileError(e))?);
+        builder.add_lun(Lun::new(self.fakefs.clone()).map_err(WMSError::FileError)?);
         let (msd, handle) = builder.build();
+        self.msd = Some(msd);
+        Ok(vec![handle])
+    }
 
-        let udc = default_udc().expect("Cannot get UDC");
-        let reg = Gadget::new(
-            Class::new(1, 2, 3),
-            Id::new(4, 5),
-            Strings::new("Cole", "evil USB", "Cereal Value"),
-        )
-        .with_config(Config::new("cfg1").with_function(handle))
-        .bind(&udc)
-        .map_err(|e| WMSError::GadgetSetupError(e))?;
-
+    fn bound(&mut self, _gadget: &BoundGadget) -> Result<(), WMSError> {
+        let msd = self.msd.as_ref().ok_or(WMSError::RuntimeError)?;
         println!("MSD device at {}", msd.status().path().unwrap().display());
-        Ok(reg)
+        Ok(())
     }
 }
 
+device_setup!(
+    WMSMassStorageDevice,
+    "Records the setup, binding and removal of the gadget in `log`"
+);
+
 impl SnoopAttack for WMSMassStorageDevice {
     fn open_logfile(&mut self, path: &str) -> Result<(), WMSError> {
         self.logfs = Some(std::path::PathBuf::from(path));
@@ -280,6 +807,7 @@ impl SnoopAttack for WMSMassStorageDevice {
     }
 
     fn snoop_attack(self) -> Result<(), WMSError> {
+        scope::authorized(self.setup.authorization.as_ref())?;
         let mut inotify = inotify::Inotify::init().expect("Could not initialize inotify");
         inotify
             .watches()
@@ -312,87 +840,16 @@ impl SnoopAttack for WMSMassStorageDevice {
 }
 
 // Utility functions
-fn str_to_keycode(c: &str) -> [u8; 8] {
-    let mut keycode = [0; 8];
-    // First handle modifiers
-    let words: Vec<&str> = c.split_whitespace().collect();
-    if words.len() == 0 {
-        println!("Invalid length for line {}", c);
-        return keycode;
-    }
-    if words.len() > 1 {
-        for modifier in words[0..words.len() - 1].iter() {
-            match *modifier {
-                "shift" => keycode[0] |= 0x02,
-                "ctrl" => keycode[0] |= 0x01,
-                "alt" => keycode[0] |= 0x04,
-                "gui" => keycode[0] |= 0x08,
-                _ => (),
-            }
-        }
-    }
+/// Returns the modifier bit for a modifier name such as "shift" or "rctrl"
+pub(crate) fn modifier_bit(name: &str) -> Option<u8> {
+    Key::from_name(name).and_then(Key::modifier_bit)
+}
 
-    // Only 1 key per line for now
-    let c = words[words.len() - 1];
-    match c {
-        "a" => keycode[2] = 4,
-        "b" => keycode[2] = 5,
-        "c" => keycode[2] = 6,
-        "d" => keycode[2] = 7,
-        "e" => keycode[2] = 8,
-        "f" => keycode[2] = 9,
-        "g" => keycode[2] = 10,
-        "h" => keycode[2] = 11,
-        "i" => keycode[2] = 12,
-        "j" => keycode[2] = 13,
-        "k" => keycode[2] = 14,
-        "l" => keycode[2] = 15,
-        "m" => keycode[2] = 16,
-        "n" => keycode[2] = 17,
-        "o" => keycode[2] = 18,
-        "p" => keycode[2] = 19,
-        "q" => keycode[2] = 20,
-        "r" => keycode[2] = 21,
-        "s" => keycode[2] = 22,
-        "t" => keycode[2] = 23,
-        "u" => keycode[2] = 24,
-        "v" => keycode[2] = 25,
-        "w" => keycode[2] = 26,
-        "x" => keycode[2] = 27,
-        "y" => keycode[2] = 28,
-        "z" => keycode[2] = 29,
-        "1" => keycode[2] = 30,
-        "2" => keycode[2] = 31,
-        "3" => keycode[2] = 32,
-        "4" => keycode[2] = 33,
-        "5" => keycode[2] = 34,
-        "6" => keycode[2] = 35,
-        "7" => keycode[2] = 36,
-        "8" => keycode[2] = 37,
-        "9" => keycode[2] = 38,
-        "0" => keycode[2] = 39,
-        "\n" => keycode[2] = 40,
-        "\t" => keycode[2] = 43,
-        " " => keycode[2] = 44,
-        "-" => keycode[2] = 45,
-        "=" => keycode[2] = 46,
-        "[" => keycode[2] = 47,
-        "]" => keycode[2] = 48,
-        "\\" => keycode[2] = 49,
-        ";" => keycode[2] = 51,
-        "\'" => keycode[2] = 52,
-        "`" => keycode[2] = 53,
-        "," => keycode[2] = 54,
-        "." => keycode[2] = 55,
-        "/" => keycode[2] = 56,
-        "enter" => keycode[2] = 40,
-        "tab" => keycode[2] = 43,
-        "meta" => keycode[2] = 231,
-        "release" => keycode[2] = 0,
-        "space" => keycode[2] = 44,
-        _ => keycode[2] = 0,
-    }
-    keycode
+/// Returns the usage ID for a key name such as "a" or "enter"
+pub(crate) fn key_code(name: &str) -> Option<u8> {
+    Key::from_name(name)
+        .filter(|key| !key.is_modifier())
+        .map(Key::usage)
 }
 
 pub fn is_keyboard(device: &InterfaceDescriptor) -> bool {
@@ -420,3 +877,62 @@ pub fn claim_interface<T: UsbContext>(handle: &mut DeviceHandle<T>, iface_num: u
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_protocol_picks_the_report_format() {
+        let mut keyboard = WMSKeyboardDevice::new();
+        keyboard.set_nkro(true);
+        keyboard.actions = parse_script("ctrl a").unwrap();
+        let lengths = |keyboard: &WMSKeyboardDevice| -> Vec<usize> {
+            let timeline = keyboard.compile();
+            timeline.entries.iter().map(|e| e.report.len()).collect()
+        };
+        assert!(lengths(&keyboard).iter().all(|len| *len == 36));
+        keyboard.set_host_protocol(HidProtocol::Boot);
+        assert!(lengths(&keyboard).iter().all(|len| *len == 8));
+        keyboard.set_nkro(false);
+        keyboard.set_host_protocol(HidProtocol::Report);
+        assert!(lengths(&keyboard).iter().all(|len| *len == 8));
+    }
+
+    #[test]
+    fn aborted_injections_are_audited() {
+        let path = std::env::temp_dir().join(format!("wms-{}-abort.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut keyboard = WMSKeyboardDevice::new();
+        let recorder = Recorder::new();
+        keyboard.set_sink(recorder.clone());
+        assert!(matches!(
+            keyboard.type_text("a"),
+            Err(WMSError::NotAuthorized)
+        ));
+
+        let scope = "engagement_id = ACME-1\nexpires = 2999-12-31\noperator = jdoe\n";
+        let authorization = Scope::parse(scope).unwrap().authorize("jdoe", None);
+        keyboard.set_authorization(authorization.unwrap()).unwrap();
+        keyboard
+            .set_audit_log(AuditLog::open(&path).unwrap())
+            .unwrap();
+        keyboard.kill_switch().abort();
+        assert!(matches!(keyboard.type_text("a"), Err(WMSError::Aborted)));
+        assert_eq!(recorder.reports(), [vec![0; 8]]);
+        drop(keyboard);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<&str> = log
+            .lines()
+            .map(|l| l.splitn(5, ' ').last().unwrap())
+            .collect();
+        assert!(events[1].starts_with("authorize operator=jdoe engagement_id=ACME-1"));
+        assert!(events[1].ends_with("signature=unchecked"));
+        assert_eq!(
+            events[2..],
+            ["report keyboard 0000000000000000", "abort", "close"]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,11 +1,19 @@
-use usb_gadget;
-use wms::{Attack, InputAttack, SnoopAttack, WMSError};
+use wms::{read_team_key, Attack, Scope, SnoopAttack};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let (Some(scope), team_key) = (args.next(), args.next()) else {
+        eprintln!("Usage: wms <scope file> [team key file]");
+        std::process::exit(2);
+    };
+    let scope = Scope::from_file(scope)?;
+    let team_key = team_key.map(read_team_key).transpose()?;
 
-fn main() -> Result<(), wms::WMSError> {
     usb_gadget::remove_all().expect("Could not remove USB gadgets");
     let mut kybd = wms::WMSKeyboardDevice::new();
-    let reg_gadget = kybd.setup_gadget()?;
-    kybd.open_logfile("./keylog");
+    kybd.set_authorization(scope.authorize(&std::env::var("USER")?, team_key.as_ref())?)?;
+    let _reg_gadget = kybd.setup_gadget()?;
+    kybd.open_logfile("./keylog")?;
     kybd.snoop_attack()?;
     Ok(())
 }
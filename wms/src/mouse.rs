@@ -0,0 +1,421 @@
+use crate::script::{suggest_from, tokenize};
+use crate::{ScriptError, WMSError};
+
+/// Report ID of the relative mouse
+const RELATIVE_ID: u8 = 1;
+/// Report ID of the absolute pointer
+const ABSOLUTE_ID: u8 = 2;
+
+/// Largest coordinate of the absolute pointer, at the right or bottom edge of the screen
+pub const ABSOLUTE_MAX: u16 = 0x7FFF;
+
+/// Length of the longest report, the absolute pointer's
+pub(crate) const MOUSE_REPORT_LEN: u8 = 7;
+
+/// Report descriptor of a relative mouse and an absolute pointer with five buttons
+///
+/// Relative reports are `[1, buttons, x, y, wheel, pan]` and absolute reports
+/// `[2, buttons, x, x, y, y, wheel]` with little endian coordinates.
+#[rustfmt::skip]
+pub const MOUSE_REPORT_DESC: [u8; 129] = [
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x02,             // Usage (Mouse)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, RELATIVE_ID,      //   Report ID
+    0x09, 0x01,             //   Usage (Pointer)
+    0xA1, 0x00,             //   Collection (Physical)
+    0x05, 0x09,             //     Usage Page (Button)
+    0x19, 0x01,             //     Usage Minimum (1)
+    0x29, 0x05,             //     Usage Maximum (5)
+    0x15, 0x00,             //     Logical Minimum (0)
+    0x25, 0x01,             //     Logical Maximum (1)
+    0x95, 0x05,             //     Report Count (5)
+    0x75, 0x01,             //     Report Size (1)
+    0x81, 0x02,             //     Input (Data, Variable, Absolute)
+    0x95, 0x01,             //     Report Count (1)
+    0x75, 0x03,             //     Report Size (3)
+    0x81, 0x03,             //     Input (Constant)
+    0x05, 0x01,             //     Usage Page (Generic Desktop)
+    0x09, 0x30,             //     Usage (X)
+    0x09, 0x31,             //     Usage (Y)
+    0x09, 0x38,             //     Usage (Wheel)
+    0x15, 0x81,             //     Logical Minimum (-127)
+    0x25, 0x7F,             //     Logical Maximum (127)
+    0x75, 0x08,             //     Report Size (8)
+    0x95, 0x03,             //     Report Count (3)
+    0x81, 0x06,             //     Input (Data, Variable, Relative)
+    0x05, 0x0C,             //     Usage Page (Consumer)
+    0x0A, 0x38, 0x02,       //     Usage (AC Pan)
+    0x95, 0x01,             //     Report Count (1)
+    0x81, 0x06,             //     Input (Data, Variable, Relative)
+    0xC0,                   //   End Collection
+    0xC0,                   // End Collection
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x02,             // Usage (Mouse)
+    0xA1, 0x01,             // Collection (Application)
+    0x85, ABSOLUTE_ID,      //   Report ID
+    0x09, 0x01,             //   Usage (Pointer)
+    0xA1, 0x00,             //   Collection (Physical)
+    0x05, 0x09,             //     Usage Page (Button)
+    0x19, 0x01,             //     Usage Minimum (1)
+    0x29, 0x05,             //     Usage Maximum (5)
+    0x15, 0x00,             //     Logical Minimum (0)
+    0x25, 0x01,             //     Logical Maximum (1)
+    0x95, 0x05,             //     Report Count (5)
+    0x75, 0x01,             //     Report Size (1)
+    0x81, 0x02,             //     Input (Data, Variable, Absolute)
+    0x95, 0x01,             //     Report Count (1)
+    0x75, 0x03,             //     Report Size (3)
+    0x81, 0x03,             //     Input (Constant)
+    0x05, 0x01,             //     Usage Page (Generic Desktop)
+    0x09, 0x30,             //     Usage (X)
+    0x09, 0x31,             //     Usage (Y)
+    0x16, 0x00, 0x00,       //     Logical Minimum (0)
+    0x26, 0xFF, 0x7F,       //     Logical Maximum (32767)
+    0x75, 0x10,             //     Report Size (16)
+    0x95, 0x02,             //     Report Count (2)
+    0x81, 0x02,             //     Input (Data, Variable, Absolute)
+    0x09, 0x38,             //     Usage (Wheel)
+    0x15, 0x81,             //     Logical Minimum (-127)
+    0x25, 0x7F,             //     Logical Maximum (127)
+    0x75, 0x08,             //     Report Size (8)
+    0x95, 0x01,             //     Report Count (1)
+    0x81, 0x06,             //     Input (Data, Variable, Relative)
+    0xC0,                   //   End Collection
+    0xC0,                   // End Collection
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+}
+
+impl MouseButton {
+    const ALL: [(MouseButton, &'static str); 5] = [
+        (MouseButton::Left, "LEFT"),
+        (MouseButton::Right, "RIGHT"),
+        (MouseButton::Middle, "MIDDLE"),
+        (MouseButton::Back, "BACK"),
+        (MouseButton::Forward, "FORWARD"),
+    ];
+
+    /// Bit of the button in a report
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Looks up a button by its name in scripts, ignoring case
+    pub fn from_name(name: &str) -> Option<MouseButton> {
+        MouseButton::ALL
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name))
+            .map(|(button, _)| *button)
+    }
+}
+
+/// A single step of a mouse attack
+#[derive(Debug, Clone, PartialEq)]
+pub enum MouseAction {
+    /// Press a button, in addition to any already held
+    Press(MouseButton),
+    Release(MouseButton),
+    /// Move the pointer by the given distance, right and down being positive
+    Move(i32, i32),
+    /// Move the pointer to a position, from 0 to [`ABSOLUTE_MAX`] on each axis
+    MoveTo(u16, u16),
+    /// Turn the wheel up and tilt it right by the given number of detents
+    Scroll(i32, i32),
+    /// Wait for the given number of milliseconds
+    Delay(u64),
+}
+
+/// The buttons held down on the host, turns mouse actions into the reports to send
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MouseState {
+    buttons: u8,
+}
+
+impl MouseState {
+    /// Applies an action, returning the reports that tell the host about it
+    ///
+    /// Moves and scrolls too far for one report are split into several.
+    pub fn apply(&mut self, action: &MouseAction) -> Vec<Vec<u8>> {
+        match action {
+            MouseAction::Press(button) => {
+                self.buttons |= button.bit();
+                vec![self.relative(0, 0, 0, 0)]
+            }
+            MouseAction::Release(button) => {
+                self.buttons &= !button.bit();
+                vec![self.relative(0, 0, 0, 0)]
+            }
+            MouseAction::Move(x, y) => split(*x, *y)
+                .map(|(x, y)| self.relative(x, y, 0, 0))
+                .collect(),
+            MouseAction::Scroll(wheel, pan) => split(*wheel, *pan)
+                .map(|(wheel, pan)| self.relative(0, 0, wheel, pan))
+                .collect(),
+            MouseAction::MoveTo(x, y) => {
+                let (x, y) = ((*x).min(ABSOLUTE_MAX), (*y).min(ABSOLUTE_MAX));
+                let [x0, x1] = x.to_le_bytes();
+                let [y0, y1] = y.to_le_bytes();
+                vec![vec![ABSOLUTE_ID, self.buttons, x0, x1, y0, y1, 0]]
+            }
+            MouseAction::Delay(_) => Vec::new(),
+        }
+    }
+
+    /// Releases every button, returning the report that tells the host about it
+    pub fn release_all(&mut self) -> Vec<u8> {
+        self.buttons = 0;
+        self.relative(0, 0, 0, 0)
+    }
+
+    fn relative(&self, x: i8, y: i8, wheel: i8, pan: i8) -> Vec<u8> {
+        vec![
+            RELATIVE_ID,
+            self.buttons,
+            x as u8,
+            y as u8,
+            wheel as u8,
+            pan as u8,
+        ]
+    }
+}
+
+/// Splits a distance into equal steps that each fit in a report
+///
+/// Works in i64, as any i32 distance times the number of steps would overflow.
+fn split(x: i32, y: i32) -> impl Iterator<Item = (i8, i8)> {
+    let (x, y) = (x as i64, y as i64);
+    let steps = (x.abs().max(y.abs()) + 126) / 127;
+    (0..steps).map(move |i| {
+        let step = |d: i64| (d * (i + 1) / steps - d * i / steps) as i8;
+        (step(x), step(y))
+    })
+}
+
+const COMMANDS: [&str; 12] = [
+    "REM",
+    "DELAY",
+    "DEFAULT_DELAY",
+    "MOVE",
+    "MOVETO",
+    "CLICK",
+    "DOUBLECLICK",
+    "PRESS",
+    "RELEASE",
+    "DRAG",
+    "SCROLL",
+    "HSCROLL",
+];
+
+/// Parses a mouse script into a list of actions
+///
+/// Each line holds one command:
+///
+/// - `MOVE <x> <y>` moves the pointer by a distance, right and down being positive
+/// - `MOVETO <x> <y>` moves the pointer to a position from 0 to 32767 on each axis
+/// - `CLICK`, `DOUBLECLICK`, `PRESS` and `RELEASE` take an optional button,
+///   one of LEFT (the default), RIGHT, MIDDLE, BACK or FORWARD
+/// - `DRAG <x> <y> [button]` moves the pointer with a button held down
+/// - `SCROLL <n>` turns the wheel up, or down for negative `n`, and `HSCROLL <n>`
+///   scrolls right or left
+/// - `DELAY`, `DEFAULT_DELAY` and `REM` work as in keyboard scripts
+///
+/// Every bad line is reported in a single [`WMSError::SyntaxError`].
+pub fn parse_mouse_script(script: &str) -> Result<Vec<MouseAction>, WMSError> {
+    let mut errors = Vec::new();
+    let mut actions = Vec::new();
+    let mut default_delay = 0;
+
+    for (i, line) in script.lines().enumerate() {
+        let tokens = tokenize(line);
+        let Some(&(column, command)) = tokens.first() else {
+            continue;
+        };
+        let mut error = |column: usize, token: &str, message: &str, suggestion: Option<&str>| {
+            errors.push(ScriptError::new(i + 1, column, token, message, suggestion))
+        };
+        let args = &tokens[1..];
+        let upper = command.to_uppercase();
+        if upper == "REM" {
+            continue;
+        }
+
+        // Check the number of arguments, then parse them
+        let (numbers, button) = match upper.as_str() {
+            "DELAY" | "DEFAULT_DELAY" | "SCROLL" | "HSCROLL" => (1, false),
+            "MOVE" | "MOVETO" => (2, false),
+            "CLICK" | "DOUBLECLICK" | "PRESS" | "RELEASE" => (0, true),
+            "DRAG" => (2, true),
+            _ => {
+                let suggestion = suggest_from(&upper, COMMANDS.iter().copied());
+                error(column, command, "unknown command", suggestion);
+                continue;
+            }
+        };
+        if args.len() < numbers || args.len() > numbers + button as usize {
+            let expected = match (numbers, button) {
+                (0, _) => "expected an optional button after".to_string(),
+                (1, false) => "expected a number after".to_string(),
+                (n, false) => format!("expected {} numbers after", n),
+                (n, true) => format!("expected {} numbers and an optional button after", n),
+            };
+            error(column, command, &expected, None);
+            continue;
+        }
+        let mut values = Vec::new();
+        for &(column, arg) in &args[..numbers] {
+            match arg.parse::<i32>() {
+                Ok(n)
+                    if n >= 0
+                        || !matches!(upper.as_str(), "DELAY" | "DEFAULT_DELAY" | "MOVETO") =>
+                {
+                    values.push(n)
+                }
+                _ => error(column, arg, "expected a number, found", None),
+            }
+        }
+        let pressed = match args.get(numbers) {
+            None => Some(MouseButton::Left),
+            Some(&(column, name)) => {
+                let button = MouseButton::from_name(name);
+                if button.is_none() {
+                    let names = MouseButton::ALL.iter().map(|(_, n)| *n);
+                    let suggestion = suggest_from(&name.to_uppercase(), names);
+                    error(column, name, "unknown button", suggestion);
+                }
+                button
+            }
+        };
+        let (Some(button), true) = (pressed, values.len() == numbers) else {
+            continue;
+        };
+
+        let mut current = match upper.as_str() {
+            "DEFAULT_DELAY" => {
+                default_delay = values[0] as u64;
+                continue;
+            }
+            "DELAY" => vec![MouseAction::Delay(values[0] as u64)],
+            "MOVE" => vec![MouseAction::Move(values[0], values[1])],
+            "MOVETO" => {
+                let clamp = |v: i32| v.min(ABSOLUTE_MAX as i32) as u16;
+                vec![MouseAction::MoveTo(clamp(values[0]), clamp(values[1]))]
+            }
+            "SCROLL" => vec![MouseAction::Scroll(values[0], 0)],
+            "HSCROLL" => vec![MouseAction::Scroll(0, values[0])],
+            "PRESS" => vec![MouseAction::Press(button)],
+            "RELEASE" => vec![MouseAction::Release(button)],
+            "CLICK" => vec![MouseAction::Press(button), MouseAction::Release(button)],
+            "DOUBLECLICK" => vec![
+                MouseAction::Press(button),
+                MouseAction::Release(button),
+                MouseAction::Press(button),
+                MouseAction::Release(button),
+            ],
+            "DRAG" => vec![
+                MouseAction::Press(button),
+                MouseAction::Move(values[0], values[1]),
+                MouseAction::Release(button),
+            ],
+            _ => unreachable!(),
+        };
+        if default_delay > 0 {
+            current.push(MouseAction::Delay(default_delay));
+        }
+        actions.extend(current);
+    }
+
+    if errors.is_empty() {
+        Ok(actions)
+    } else {
+        Err(WMSError::SyntaxError(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(script: &str) -> Vec<ScriptError> {
+        match parse_mouse_script(script) {
+            Err(WMSError::SyntaxError(errors)) => errors,
+            other => panic!("expected syntax errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let actions = parse_mouse_script(
+            "REM corner\nMOVETO 0 40000\nclick right\nDRAG 10 -5\nSCROLL -2\nHSCROLL 1\nDELAY 30",
+        )
+        .unwrap();
+        use MouseAction::*;
+        let expected = [
+            MoveTo(0, ABSOLUTE_MAX),
+            Press(MouseButton::Right),
+            Release(MouseButton::Right),
+            Press(MouseButton::Left),
+            Move(10, -5),
+            Release(MouseButton::Left),
+            Scroll(-2, 0),
+            Scroll(0, 1),
+            Delay(30),
+        ];
+        assert_eq!(actions, expected);
+    }
+
+    #[test]
+    fn default_delay() {
+        let actions = parse_mouse_script("DEFAULT_DELAY 5\nDOUBLECLICK").unwrap();
+        assert_eq!(actions.len(), 5);
+        assert_eq!(actions[4], MouseAction::Delay(5));
+    }
+
+    #[test]
+    fn errors_have_their_column() {
+        let found: Vec<_> = errors("MOVE 1\nCLICK TOP\nMOVETO -1 0\nSCROL 1")
+            .iter()
+            .map(|e| (e.line, e.column, e.token.clone(), e.suggestion.clone()))
+            .collect();
+        let expected = [
+            (1, 1, "MOVE".to_string(), None),
+            (2, 7, "TOP".to_string(), None),
+            (3, 8, "-1".to_string(), None),
+            (4, 1, "SCROL".to_string(), Some("SCROLL".to_string())),
+        ];
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn reports() {
+        let mut state = MouseState::default();
+        let press = state.apply(&MouseAction::Press(MouseButton::Middle));
+        assert_eq!(press, [vec![RELATIVE_ID, 0x04, 0, 0, 0, 0]]);
+        let moved = state.apply(&MouseAction::Move(-3, 2));
+        assert_eq!(moved, [vec![RELATIVE_ID, 0x04, 0xFD, 2, 0, 0]]);
+        let to = state.apply(&MouseAction::MoveTo(0x1234, ABSOLUTE_MAX));
+        assert_eq!(to, [vec![ABSOLUTE_ID, 0x04, 0x34, 0x12, 0xFF, 0x7F, 0]]);
+        assert_eq!(state.release_all(), [RELATIVE_ID, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn far_moves_are_split() {
+        let reports = MouseState::default().apply(&MouseAction::Move(300, -10));
+        let steps: Vec<(i8, i8)> = reports.iter().map(|r| (r[2] as i8, r[3] as i8)).collect();
+        assert_eq!(steps, [(100, -3), (100, -3), (100, -4)]);
+        let total = |f: fn(&(i8, i8)) -> i8| steps.iter().map(|s| f(s) as i32).sum::<i32>();
+        assert_eq!((total(|s| s.0), total(|s| s.1)), (300, -10));
+
+        let (x, y) = split(i32::MIN, i32::MAX).fold((0i64, 0i64), |(x, y), (dx, dy)| {
+            (x + dx as i64, y + dy as i64)
+        });
+        assert_eq!((x, y), (i32::MIN as i64, i32::MAX as i64));
+    }
+}
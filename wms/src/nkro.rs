@@ -0,0 +1,115 @@
+use crate::Chord;
+
+/// Length of an N-key rollover report: a boot report followed by the key bitmap
+pub(crate) const NKRO_REPORT_LEN: u8 = 36;
+
+/// Report descriptor of an N-key rollover keyboard
+///
+/// Reports start like boot reports, with the modifier bits, a reserved byte and
+/// six key slots, followed by a bitmap with one bit for each usage from 0x00 to
+/// 0xDF. Hosts that read the descriptor take keys from both, while hosts that
+/// ignore it and use the boot protocol, such as BIOSes, read the first 8 bytes.
+/// Those hosts still only see six keys at a time, and a host that rejects
+/// reports longer than 8 bytes needs [`HidProtocol::Boot`] instead.
+#[rustfmt::skip]
+pub const NKRO_REPORT_DESC: [u8; 71] = [
+    0x05, 0x01,             // Usage Page (Generic Desktop)
+    0x09, 0x06,             // Usage (Keyboard)
+    0xA1, 0x01,             // Collection (Application)
+    0x05, 0x07,             //   Usage Page (Keyboard)
+    0x19, 0xE0,             //   Usage Minimum (Left Control)
+    0x29, 0xE7,             //   Usage Maximum (Right GUI)
+    0x15, 0x00,             //   Logical Minimum (0)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x75, 0x01,             //   Report Size (1)
+    0x95, 0x08,             //   Report Count (8)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0x95, 0x01,             //   Report Count (1)
+    0x75, 0x08,             //   Report Size (8)
+    0x81, 0x01,             //   Input (Constant)
+    0x05, 0x08,             //   Usage Page (LEDs)
+    0x19, 0x01,             //   Usage Minimum (Num Lock)
+    0x29, 0x05,             //   Usage Maximum (Kana)
+    0x95, 0x05,             //   Report Count (5)
+    0x75, 0x01,             //   Report Size (1)
+    0x91, 0x02,             //   Output (Data, Variable, Absolute)
+    0x95, 0x01,             //   Report Count (1)
+    0x75, 0x03,             //   Report Size (3)
+    0x91, 0x01,             //   Output (Constant)
+    0x05, 0x07,             //   Usage Page (Keyboard)
+    0x19, 0x00,             //   Usage Minimum (0)
+    0x29, 0xDF,             //   Usage Maximum (0xDF)
+    0x26, 0xDF, 0x00,       //   Logical Maximum (0xDF)
+    0x75, 0x08,             //   Report Size (8)
+    0x95, 0x06,             //   Report Count (6)
+    0x81, 0x00,             //   Input (Data, Array, Absolute)
+    0x25, 0x01,             //   Logical Maximum (1)
+    0x75, 0x01,             //   Report Size (1)
+    0x96, 0xE0, 0x00,       //   Report Count (224)
+    0x81, 0x02,             //   Input (Data, Variable, Absolute)
+    0xC0,                   // End Collection
+];
+
+/// Protocol a host has selected for a boot interface with SET_PROTOCOL
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HidProtocol {
+    /// 8-byte boot keyboard reports, used by BIOSes and other simple hosts
+    Boot,
+    /// Reports as described by the report descriptor, which devices start in
+    #[default]
+    Report,
+}
+
+/// Converts a chord to an N-key rollover report
+///
+/// The first 8 bytes are the chord's [`Chord::boot_report`], so with more than
+/// six keys down its slots hold ErrorRollOver and only the bitmap names them.
+pub fn nkro_report(keys: &Chord) -> Vec<u8> {
+    let mut report = keys.boot_report().to_vec();
+    report.extend_from_slice(keys.bitmap());
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds up the bits of the input items in a report descriptor
+    fn input_bits(desc: &[u8]) -> usize {
+        let (mut size, mut count, mut bits, mut i) = (0, 0, 0, 0);
+        while i < desc.len() {
+            let len = [0, 1, 2, 4][(desc[i] & 0x03) as usize];
+            let data = (desc[i + 1..i + 1 + len].iter().rev())
+                .fold(0usize, |value, byte| value << 8 | *byte as usize);
+            match desc[i] & 0xFC {
+                0x74 => size = data,
+                0x94 => count = data,
+                0x80 => bits += size * count,
+                _ => (),
+            }
+            i += 1 + len;
+        }
+        bits
+    }
+
+    #[test]
+    fn descriptor_matches_reports() {
+        assert_eq!(input_bits(&NKRO_REPORT_DESC), NKRO_REPORT_LEN as usize * 8);
+        let report = nkro_report(&Chord::default());
+        assert_eq!(report.len(), NKRO_REPORT_LEN as usize);
+    }
+
+    #[test]
+    fn reports_start_with_a_boot_report() {
+        let keys = Chord::new(0x02, &[0x04, 0x05]);
+        let report = nkro_report(&keys);
+        assert_eq!(report[..8], [0x02, 0, 0x04, 0x05, 0, 0, 0, 0]);
+        assert_eq!(report[8], 0b0011_0000);
+        assert!(report[9..].iter().all(|byte| *byte == 0));
+
+        let seven = Chord::new(0, &[0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A]);
+        let report = nkro_report(&seven);
+        assert_eq!(report[2..8], [0x01; 6]);
+        assert_eq!(report[8..10], [0b1111_0000, 0b0000_0111]);
+    }
+}
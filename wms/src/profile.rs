@@ -0,0 +1,217 @@
+use usb_gadget::{Class, Config, Gadget, Id, Strings};
+
+use crate::WMSError;
+
+/// Most power a USB 2.0 device may draw from the host, in mA
+const MAX_POWER: u16 = 500;
+
+/// Identity and descriptor fields of a gadget
+///
+/// Profiles can be loaded from files of `key = value` lines, so each test bench
+/// can use its own IDs without a patched build:
+///
+/// ```text
+/// # Lines starting with # are comments
+/// vendor_id = 0x1209
+/// product_id = 0x0001
+/// manufacturer = Lab
+/// product = Bench keyboard
+/// serial_number = 0001
+/// device_release = 0x0100
+/// max_power = 100
+/// self_powered = false
+/// ```
+///
+/// `class`, `sub_class`, `protocol` and `remote_wakeup` can be set the same way.
+/// Keys left out keep their default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GadgetProfile {
+    pub class: u8,
+    pub sub_class: u8,
+    pub protocol: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: String,
+    pub product: String,
+    pub serial_number: String,
+    /// bcdDevice
+    pub device_release: u16,
+    /// Maximum power drawn from the host in mA, at most 500
+    pub max_power: u16,
+    pub self_powered: bool,
+    pub remote_wakeup: bool,
+}
+
+impl Default for GadgetProfile {
+    fn default() -> Self {
+        GadgetProfile {
+            class: 1,
+            sub_class: 2,
+            protocol: 3,
+            vendor_id: 4,
+            product_id: 5,
+            manufacturer: "Cole".to_string(),
+            product: "evil USB".to_string(),
+            serial_number: "Cereal Value".to_string(),
+            device_release: 0,
+            max_power: 500,
+            self_powered: false,
+            remote_wakeup: false,
+        }
+    }
+}
+
+impl GadgetProfile {
+    /// Reads a profile file, see [`GadgetProfile`] for the format
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<GadgetProfile, WMSError> {
+        let profile = std::fs::read_to_string(path).map_err(WMSError::FileError)?;
+        GadgetProfile::parse(&profile)
+    }
+
+    /// Parses the `key = value` lines of a profile
+    pub fn parse(profile: &str) -> Result<GadgetProfile, WMSError> {
+        let mut result = GadgetProfile::default();
+        read_fields(profile, WMSError::ProfileError, |key, value| {
+            let bad_value = || format!("bad value `{}` for {}", value, key);
+            match key {
+                "class" => result.class = number(value).ok_or_else(bad_value)?,
+                "sub_class" => result.sub_class = number(value).ok_or_else(bad_value)?,
+                "protocol" => result.protocol = number(value).ok_or_else(bad_value)?,
+                "vendor_id" => result.vendor_id = number(value).ok_or_else(bad_value)?,
+                "product_id" => result.product_id = number(value).ok_or_else(bad_value)?,
+                "manufacturer" => result.manufacturer = value.to_string(),
+                "product" => result.product = value.to_string(),
+                "serial_number" => result.serial_number = value.to_string(),
+                "device_release" => result.device_release = number(value).ok_or_else(bad_value)?,
+                "max_power" => {
+                    result.max_power = number(value)
+                        .filter(|ma| *ma <= MAX_POWER)
+                        .ok_or_else(|| format!("max_power {} is not 0 to 500 mA", value))?
+                }
+                "self_powered" => result.self_powered = value.parse().map_err(|_| bad_value())?,
+                "remote_wakeup" => result.remote_wakeup = value.parse().map_err(|_| bad_value())?,
+                other => return Err(format!("unknown key `{}`", other)),
+            }
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    /// Builds a gadget with this identity and no configurations
+    pub(crate) fn gadget(&self) -> Gadget {
+        let mut gadget = Gadget::new(
+            Class::new(self.class, self.sub_class, self.protocol),
+            Id::new(self.vendor_id, self.product_id),
+            Strings::new(&self.manufacturer, &self.product, &self.serial_number),
+        );
+        gadget.device_release = self.device_release;
+        gadget
+    }
+
+    /// Builds an empty configuration with this power setup
+    pub(crate) fn config(&self) -> Result<Config, WMSError> {
+        let mut config = Config::new("cfg1");
+        config
+            .set_max_power_ma(self.max_power)
+            .map_err(|e| WMSError::ProfileError(format!("max_power: {}", e)))?;
+        config.self_powered = self.self_powered;
+        config.remote_wakeup = self.remote_wakeup;
+        Ok(config)
+    }
+}
+
+/// Reads `key = value` lines, the format of profiles, scopes and bundle manifests
+///
+/// Blank lines and lines starting with `#` are skipped. `field` gets each trimmed
+/// key and value, and the messages it fails with become `error`s along with the
+/// line number.
+pub(crate) fn read_fields(
+    text: &str,
+    error: fn(String) -> WMSError,
+    mut field: impl FnMut(&str, &str) -> Result<(), String>,
+) -> Result<(), WMSError> {
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let at_line = |message: String| error(format!("line {}: {}", i + 1, message));
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at_line("expected `key = value`".to_string()))?;
+        field(key.trim(), value.trim()).map_err(at_line)?;
+    }
+    Ok(())
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number
+fn number<T: TryFrom<u64>>(value: &str) -> Option<T> {
+    let n = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => value.parse().ok()?,
+    };
+    T::try_from(n).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(profile: &str) -> String {
+        match GadgetProfile::parse(profile) {
+            Err(WMSError::ProfileError(message)) => message,
+            other => panic!("expected a profile error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let profile = GadgetProfile::parse(
+            "# bench\n\nvendor_id = 0x1209\nproduct_id = 1\nproduct = Bench keyboard\n\
+             device_release = 0X0100\nmax_power = 100\nself_powered = true\n",
+        )
+        .unwrap();
+        assert_eq!(profile.vendor_id, 0x1209);
+        assert_eq!(profile.product_id, 1);
+        assert_eq!(profile.product, "Bench keyboard");
+        assert_eq!(profile.device_release, 0x0100);
+        assert_eq!(profile.max_power, 100);
+        assert!(profile.self_powered);
+        assert_eq!(profile.manufacturer, GadgetProfile::default().manufacturer);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("vendor_id = 0x12345"),
+            "line 1: bad value `0x12345` for vendor_id"
+        );
+        assert_eq!(
+            error("\nmax_power = 501"),
+            "line 2: max_power 501 is not 0 to 500 mA"
+        );
+        assert_eq!(error("colour = red"), "line 1: unknown key `colour`");
+        assert_eq!(error("product"), "line 1: expected `key = value`");
+        assert_eq!(
+            error("self_powered = yes"),
+            "line 1: bad value `yes` for self_powered"
+        );
+    }
+
+    #[test]
+    fn config() {
+        let profile = GadgetProfile {
+            max_power: 100,
+            ..GadgetProfile::default()
+        };
+        assert!(profile.config().is_ok());
+        let too_much = GadgetProfile {
+            max_power: 600,
+            ..GadgetProfile::default()
+        };
+        assert!(matches!(too_much.config(), Err(WMSError::ProfileError(_))));
+    }
+}
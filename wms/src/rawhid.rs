@@ -0,0 +1,229 @@
+use crate::script::{suggest_from, tokenize, MAX_REPEAT};
+use crate::{ScriptError, WMSError};
+
+/// A single step of a raw HID attack
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawAction {
+    /// Send a report as is, including its report ID if the descriptor has them
+    Report(Vec<u8>),
+    /// Wait for the given number of milliseconds
+    Delay(u64),
+}
+
+/// Reads a report descriptor, either raw bytes or hex text
+///
+/// Hex text is what [`parse_hex`] accepts, e.g. `05 01 09 05 A1 01 ...`.
+/// Anything else is taken to be the binary descriptor.
+pub fn read_report_desc(path: impl AsRef<std::path::Path>) -> Result<Vec<u8>, WMSError> {
+    let bytes = std::fs::read(path).map_err(WMSError::FileError)?;
+    let hex = std::str::from_utf8(&bytes).ok().and_then(parse_hex);
+    Ok(hex.filter(|desc| !desc.is_empty()).unwrap_or(bytes))
+}
+
+/// Parses hex bytes separated by whitespace or commas
+///
+/// Bytes may have a `0x` or `0X` prefix, and `#` or `//` start a comment that runs to
+/// the end of the line. Returns `None` if anything else is found.
+pub fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let line = line.split("//").next().unwrap_or("");
+        for token in line.split(|c: char| c.is_whitespace() || c == ',') {
+            let token = token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token);
+            if token.is_empty() {
+                continue;
+            }
+            // Bytes may also be written without spaces, e.g. 0501
+            if token.len() % 2 != 0 || !token.is_ascii() {
+                return None;
+            }
+            for i in (0..token.len()).step_by(2) {
+                bytes.push(u8::from_str_radix(&token[i..i + 2], 16).ok()?);
+            }
+        }
+    }
+    Some(bytes)
+}
+
+/// Writes bytes as lowercase hex, two digits each, with `separator` between them
+pub(crate) fn hex(bytes: &[u8], separator: &str) -> String {
+    let digits: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    digits.join(separator)
+}
+
+const COMMANDS: [&str; 5] = ["REM", "DELAY", "DEFAULT_DELAY", "REPEAT", "REPORT"];
+
+/// Parses a raw HID script into a list of actions
+///
+/// Each report is a line of hex bytes, optionally starting with `REPORT`, e.g.
+/// `REPORT 01 00 7f 7f`. Reports can't be longer than `report_len`.
+/// `DELAY`, `DEFAULT_DELAY`, `REPEAT` and `REM` work as in keyboard scripts.
+///
+/// Every bad line is reported in a single [`WMSError::SyntaxError`].
+pub fn parse_raw_script(script: &str, report_len: u8) -> Result<Vec<RawAction>, WMSError> {
+    let mut errors = Vec::new();
+    let mut actions = Vec::new();
+    let mut default_delay = 0;
+    let mut last: Vec<RawAction> = Vec::new();
+
+    for (i, line) in script.lines().enumerate() {
+        let tokens = tokenize(line);
+        let Some(&(column, command)) = tokens.first() else {
+            continue;
+        };
+        let mut error = |column: usize, token: &str, message: &str, suggestion: Option<&str>| {
+            errors.push(ScriptError::new(i + 1, column, token, message, suggestion))
+        };
+        let upper = command.to_uppercase();
+        // The argument of DELAY, DEFAULT_DELAY and REPEAT
+        let number = match tokens[1..] {
+            [(column, arg)] => arg
+                .parse::<u64>()
+                .map_err(|_| (column, arg, "expected a number, found")),
+            _ => Err((column, command, "expected a number after")),
+        };
+        if let (Err((column, token, message)), "DELAY" | "DEFAULT_DELAY" | "REPEAT") =
+            (number, upper.as_str())
+        {
+            error(column, token, message, None);
+        }
+
+        let mut current = match upper.as_str() {
+            "REM" => continue,
+            "DEFAULT_DELAY" => {
+                default_delay = number.unwrap_or(default_delay);
+                continue;
+            }
+            "DELAY" => match number {
+                Ok(ms) => vec![RawAction::Delay(ms)],
+                Err(_) => continue,
+            },
+            "REPEAT" => {
+                if last.is_empty() {
+                    error(column, command, "nothing to repeat before", None);
+                }
+                let count = match (number, tokens.get(1)) {
+                    (Ok(count), Some(&(column, arg))) if count > MAX_REPEAT => {
+                        let message = format!("repeat count above the limit of {}:", MAX_REPEAT);
+                        error(column, arg, &message, None);
+                        0
+                    }
+                    (count, _) => count.unwrap_or(0),
+                };
+                for _ in 0..count {
+                    actions.extend_from_slice(&last);
+                }
+                continue;
+            }
+            _ => {
+                let hex = line.trim_start();
+                let (hex, hex_column) = match upper.as_str() {
+                    "REPORT" => (&hex[command.len()..], column + command.len() + 1),
+                    _ => (hex, column),
+                };
+                match parse_hex(hex) {
+                    Some(report) if report.is_empty() => {
+                        error(column, command, "expected hex bytes after", None);
+                        continue;
+                    }
+                    Some(report) if report.len() > report_len as usize => {
+                        let message = format!("report longer than {} bytes:", report_len);
+                        error(hex_column, hex.trim(), &message, None);
+                        continue;
+                    }
+                    Some(report) => vec![RawAction::Report(report)],
+                    None if upper == "REPORT" => {
+                        error(hex_column, hex.trim(), "expected hex bytes, found", None);
+                        continue;
+                    }
+                    None => {
+                        let suggestion = suggest_from(&upper, COMMANDS.iter().copied());
+                        error(column, command, "unknown command", suggestion);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        if default_delay > 0 {
+            current.push(RawAction::Delay(default_delay));
+        }
+        actions.extend_from_slice(&current);
+        last = current;
+    }
+
+    if errors.is_empty() {
+        Ok(actions)
+    } else {
+        Err(WMSError::SyntaxError(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(script: &str) -> Vec<ScriptError> {
+        match parse_raw_script(script, 4) {
+            Err(WMSError::SyntaxError(errors)) => errors,
+            other => panic!("expected syntax errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hex() {
+        let text = "05 01, 0x09 0X06 # keyboard\nA101 // collection\n";
+        assert_eq!(
+            parse_hex(text),
+            Some(vec![0x05, 0x01, 0x09, 0x06, 0xA1, 0x01])
+        );
+        assert_eq!(parse_hex("0x5"), None);
+        assert_eq!(parse_hex("zz"), None);
+        assert_eq!(super::hex(&[0x0A, 0xFF], " "), "0a ff");
+    }
+
+    #[test]
+    fn reports_and_repeats() {
+        let actions = parse_raw_script("REM pad\n01 02\nREPORT 0x03\nREPEAT 2\nDELAY 9", 4);
+        use RawAction::*;
+        let expected = [
+            Report(vec![1, 2]),
+            Report(vec![3]),
+            Report(vec![3]),
+            Report(vec![3]),
+            Delay(9),
+        ];
+        assert_eq!(actions.unwrap(), expected);
+        let actions = parse_raw_script("DEFAULT_DELAY 5\n01", 4).unwrap();
+        assert_eq!(actions, [Report(vec![1]), Delay(5)]);
+    }
+
+    #[test]
+    fn errors_have_their_column() {
+        let found: Vec<_> = errors("01 02 03 04 05\nREPORT xy\nREPORT\nDELAI 1\nREPEAT 1")
+            .iter()
+            .map(|e| (e.line, e.column, e.token.clone(), e.suggestion.clone()))
+            .collect();
+        let expected = [
+            (1, 1, "01 02 03 04 05".to_string(), None),
+            (2, 8, "xy".to_string(), None),
+            (3, 1, "REPORT".to_string(), None),
+            (4, 1, "DELAI".to_string(), Some("DELAY".to_string())),
+            (5, 1, "REPEAT".to_string(), None),
+        ];
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn repeat_over_limit() {
+        let errors = errors(&format!("01\nREPEAT {}", MAX_REPEAT + 1));
+        assert_eq!((errors[0].line, errors[0].column), (2, 8));
+        assert!(errors[0].message.contains("limit"));
+        let within = parse_raw_script(&format!("01\nREPEAT {}", MAX_REPEAT), 4);
+        assert_eq!(within.unwrap().len(), MAX_REPEAT as usize + 1);
+    }
+}
@@ -0,0 +1,301 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::profile::read_fields;
+use crate::{parse_hex, AuditLog, WMSError};
+
+/// Rules of engagement an attack runs under
+///
+/// Scopes are files of `key = value` lines, like gadget profiles:
+///
+/// ```text
+/// # Lines starting with # are comments
+/// engagement_id = ACME-2026-014
+/// expires = 2026-12-31
+/// operator = jdoe
+/// signature = 5f0c...
+/// ```
+///
+/// `expires` is the last day, in UTC, the engagement allows attacks on.
+/// `signature` is a hex ed25519 signature of every other line of the file, each
+/// ending in a newline, made with the team's key. It is only needed when the
+/// scope is checked against a team key, see [`Scope::authorize`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub engagement_id: String,
+    /// Days since 1970-01-01
+    pub expires: i64,
+    pub operator: String,
+    pub signature: Option<Vec<u8>>,
+    /// The lines the signature covers
+    signed: String,
+}
+
+/// Proof that a [`Scope`] allowed an operator to run attacks
+///
+/// Devices refuse to attack until they are given one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authorization {
+    engagement_id: String,
+    expires: i64,
+    operator: String,
+    /// Whether the scope's signature was checked against a team key
+    signed: bool,
+}
+
+impl Scope {
+    /// Reads a scope file, see [`Scope`] for the format
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Scope, WMSError> {
+        let scope = std::fs::read_to_string(path).map_err(WMSError::ScopeMissing)?;
+        Scope::parse(&scope)
+    }
+
+    /// Parses the `key = value` lines of a scope
+    pub fn parse(scope: &str) -> Result<Scope, WMSError> {
+        let mut engagement_id = None;
+        let mut expires = None;
+        let mut operator = None;
+        let mut signature = None;
+        read_fields(scope, WMSError::ScopeError, |key, value| {
+            let bad_value = || format!("bad value `{}` for {}", value, key);
+            match key {
+                "engagement_id" => engagement_id = Some(value.to_string()),
+                "expires" => expires = Some(parse_date(value).ok_or_else(bad_value)?),
+                "operator" => operator = Some(value.to_string()),
+                "signature" => {
+                    let bytes = parse_hex(value).filter(|b| b.len() == 64);
+                    signature = Some(bytes.ok_or_else(bad_value)?);
+                }
+                other => return Err(format!("unknown key `{}`", other)),
+            }
+            Ok(())
+        })?;
+        let mut signed = String::new();
+        for line in scope.lines() {
+            if line.split('=').next().unwrap_or("").trim() != "signature" {
+                signed.push_str(line);
+                signed.push('\n');
+            }
+        }
+        let missing = |key: &str| WMSError::ScopeError(format!("missing {}", key));
+        Ok(Scope {
+            engagement_id: engagement_id
+                .filter(|id| !id.is_empty())
+                .ok_or_else(|| missing("engagement_id"))?,
+            expires: expires.ok_or_else(|| missing("expires"))?,
+            operator: operator
+                .filter(|op| !op.is_empty())
+                .ok_or_else(|| missing("operator"))?,
+            signature,
+            signed,
+        })
+    }
+
+    /// Checks that `operator` may run attacks today, and that the scope was signed with `team_key`
+    ///
+    /// Without a team key the signature isn't checked, so unsigned scopes are
+    /// allowed. Devices record which it was in their audit log.
+    pub fn authorize(
+        &self,
+        operator: &str,
+        team_key: Option<&[u8; 32]>,
+    ) -> Result<Authorization, WMSError> {
+        if let Some(team_key) = team_key {
+            let signature = self.signature.as_deref().ok_or(WMSError::ScopeUnsigned)?;
+            let key =
+                VerifyingKey::from_bytes(team_key).map_err(|_| WMSError::BadScopeSignature)?;
+            let signature =
+                Signature::from_slice(signature).map_err(|_| WMSError::BadScopeSignature)?;
+            key.verify_strict(self.signed.as_bytes(), &signature)
+                .map_err(|_| WMSError::BadScopeSignature)?;
+        }
+        if operator != self.operator {
+            return Err(WMSError::OperatorNotAllowed(operator.to_string()));
+        }
+        let authorization = Authorization {
+            engagement_id: self.engagement_id.clone(),
+            expires: self.expires,
+            operator: operator.to_string(),
+            signed: team_key.is_some(),
+        };
+        authorization.check()?;
+        Ok(authorization)
+    }
+}
+
+impl Authorization {
+    /// ID of the engagement the attacks belong to
+    pub fn engagement_id(&self) -> &str {
+        &self.engagement_id
+    }
+
+    /// Records the grant in `log`, if there is one
+    pub(crate) fn record(&self, log: Option<&AuditLog>) -> Result<(), WMSError> {
+        let Some(log) = log else {
+            return Ok(());
+        };
+        log.record(&format!(
+            "authorize operator={} engagement_id={} expires={} signature={}",
+            self.operator.escape_debug(),
+            self.engagement_id.escape_debug(),
+            format_date(self.expires),
+            if self.signed { "checked" } else { "unchecked" }
+        ))
+    }
+
+    /// Fails once the engagement has expired
+    pub fn check(&self) -> Result<(), WMSError> {
+        if today() > self.expires {
+            return Err(WMSError::ScopeExpired(format_date(self.expires)));
+        }
+        Ok(())
+    }
+}
+
+/// Fails unless a device was given an authorization that is still valid
+pub(crate) fn authorized(authorization: Option<&Authorization>) -> Result<(), WMSError> {
+    authorization.ok_or(WMSError::NotAuthorized)?.check()
+}
+
+/// Reads a team public key, 32 bytes in raw or hex form
+pub fn read_team_key(path: impl AsRef<std::path::Path>) -> Result<[u8; 32], WMSError> {
+    let bytes = std::fs::read(path).map_err(WMSError::FileError)?;
+    let hex = std::str::from_utf8(&bytes).ok().and_then(parse_hex);
+    hex.unwrap_or(bytes)
+        .try_into()
+        .map_err(|_| WMSError::ScopeError("team key is not 32 bytes".to_string()))
+}
+
+/// Days since 1970-01-01 in UTC
+fn today() -> i64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_secs() / 86400) as i64
+}
+
+/// Parses a `YYYY-MM-DD` date into days since 1970-01-01
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    // Counts from March, so leap days come at the end of the year
+    let (y, m) = if month > 2 {
+        (year, month - 3)
+    } else {
+        (year - 1, month + 9)
+    };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some(era * 146097 + doe - 719468)
+}
+
+/// Formats days since 1970-01-01 as `YYYY-MM-DD`
+fn format_date(days: i64) -> String {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let m = (5 * doy + 2) / 153;
+    let day = doy - (153 * m + 2) / 5 + 1;
+    let month = if m < 10 { m + 3 } else { m - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const SCOPE: &str = "engagement_id = ACME-1\nexpires = 2999-12-31\noperator = jdoe\n";
+
+    fn signed(scope: &str, key: &SigningKey) -> Scope {
+        let signature = key.sign(scope.as_bytes()).to_bytes();
+        let signature: String = signature.iter().map(|b| format!("{:02x}", b)).collect();
+        Scope::parse(&format!("{}signature = {}\n", scope, signature)).unwrap()
+    }
+
+    #[test]
+    fn signatures_are_checked_against_a_team_key() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let team_key = key.verifying_key().to_bytes();
+        let scope = signed(SCOPE, &key);
+        assert!(scope.authorize("jdoe", Some(&team_key)).unwrap().signed);
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes();
+        let result = scope.authorize("jdoe", Some(&other));
+        assert!(matches!(result, Err(WMSError::BadScopeSignature)));
+        let edited = Scope {
+            operator: "eve".to_string(),
+            signed: scope.signed.replace("jdoe", "eve"),
+            ..scope
+        };
+        let result = edited.authorize("eve", Some(&team_key));
+        assert!(matches!(result, Err(WMSError::BadScopeSignature)));
+    }
+
+    #[test]
+    fn unsigned_scopes_need_no_team_key() {
+        let scope = Scope::parse(SCOPE).unwrap();
+        let authorization = scope.authorize("jdoe", None).unwrap();
+        assert_eq!(authorization.engagement_id(), "ACME-1");
+        assert!(!authorization.signed);
+        let team_key = [0; 32];
+        let result = scope.authorize("jdoe", Some(&team_key));
+        assert!(matches!(result, Err(WMSError::ScopeUnsigned)));
+        let result = scope.authorize("eve", None);
+        assert!(matches!(result, Err(WMSError::OperatorNotAllowed(_))));
+    }
+
+    #[test]
+    fn expired_scopes_are_refused() {
+        let scope = Scope::parse(&SCOPE.replace("2999-12-31", "2020-01-01")).unwrap();
+        let result = scope.authorize("jdoe", None);
+        assert!(matches!(result, Err(WMSError::ScopeExpired(date)) if date == "2020-01-01"));
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        for (date, days) in [
+            ("1970-01-01", 0),
+            ("1969-12-31", -1),
+            ("2000-02-29", 11016),
+            ("2024-03-01", 19783),
+        ] {
+            assert_eq!(parse_date(date), Some(days));
+            assert_eq!(format_date(days), date);
+        }
+        for days in (0..800_000).step_by(7) {
+            assert_eq!(parse_date(&format_date(days)), Some(days));
+        }
+    }
+
+    #[test]
+    fn bad_dates() {
+        for date in [
+            "2023-02-29",
+            "1900-02-29",
+            "2024-13-01",
+            "2024-04-31",
+            "2024-01",
+            "soon",
+        ] {
+            assert_eq!(parse_date(date), None, "{}", date);
+        }
+        assert_eq!(parse_date("2024-02-29"), Some(19782));
+    }
+}
@@ -0,0 +1,720 @@
+use crate::{
+    key_code, modifier_bit, Chord, Control, Key, KeyStroke, KeyboardLayout, LedState, LedWait,
+    Mapping, WMSError,
+};
+
+/// Most keys other than modifiers a boot protocol report can hold
+pub const BOOT_ROLLOVER: usize = 6;
+
+/// A single step of an input attack
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Press keys, in addition to any already held
+    Press(Chord),
+    /// Release keys, leaving any others held
+    Release(Chord),
+    /// Release every key
+    ReleaseAll,
+    /// Wait for the given number of milliseconds
+    Delay(u64),
+    /// Wait for the host to turn lock LEDs on or off, or to change one of them
+    WaitForLeds(LedState, LedWait),
+    /// Remember the host's lock LEDs
+    SaveLockState,
+    /// Toggle lock keys until the host's LEDs are back to the saved state
+    RestoreLockState,
+    /// Start typing text, which CapsLock on the host would change
+    BeginText,
+    /// Stop typing text
+    EndText,
+    /// Press a media or power key, which is sent apart from the keyboard's reports
+    PressControl(Control),
+    ReleaseControl(Control),
+}
+
+/// The keys held down on the host, turns actions into the reports to send
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KeyState {
+    held: Chord,
+}
+
+impl KeyState {
+    /// The keys held right now
+    pub fn held(&self) -> Chord {
+        self.held
+    }
+
+    /// The boot protocol report describing the keys held right now
+    pub fn report(&self) -> [u8; 8] {
+        self.held.boot_report()
+    }
+
+    /// Returns whether pressing `keys` keeps within `rollover` keys held at once
+    pub fn fits(&self, keys: &Chord, rollover: usize) -> bool {
+        self.held.union(keys).len() <= rollover
+    }
+
+    /// Applies a press or release, returning the keys held afterwards
+    ///
+    /// Delays and other actions that don't change the keys held return `None`.
+    pub fn apply(&mut self, action: &Action) -> Option<Chord> {
+        match action {
+            Action::Press(keys) => self.held = self.held.union(keys),
+            Action::Release(keys) => self.held = self.held.without(keys),
+            Action::ReleaseAll => self.held = Chord::default(),
+            Action::Delay(_)
+            | Action::WaitForLeds(..)
+            | Action::SaveLockState
+            | Action::RestoreLockState
+            | Action::BeginText
+            | Action::EndText
+            | Action::PressControl(_)
+            | Action::ReleaseControl(_) => return None,
+        }
+        Some(self.held)
+    }
+}
+
+/// A problem found on one line of a script
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column of the offending token, starting at 1
+    pub column: usize,
+    pub token: String,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl ScriptError {
+    pub(crate) fn new(
+        line: usize,
+        column: usize,
+        token: &str,
+        message: &str,
+        suggestion: Option<&str>,
+    ) -> ScriptError {
+        ScriptError {
+            line,
+            column,
+            token: token.to_string(),
+            message: message.to_string(),
+            suggestion: suggestion.map(|s| s.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {} `{}`",
+            self.line, self.column, self.message, self.token
+        )?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Most times a line can be repeated, so a typo can't fill the memory with copies
+pub(crate) const MAX_REPEAT: u64 = 10_000;
+
+const COMMANDS: [&str; 25] = [
+    "REM",
+    "HOLD",
+    "RELEASE",
+    "LAYOUT",
+    "STRING",
+    "STRINGLN",
+    "DELAY",
+    "DEFAULT_DELAY",
+    "DEFAULTDELAY",
+    "REPEAT",
+    "REPLAY",
+    "CONSUMER",
+    "WAIT_FOR_CAPS_ON",
+    "WAIT_FOR_CAPS_OFF",
+    "WAIT_FOR_CAPS_CHANGE",
+    "WAIT_FOR_NUM_ON",
+    "WAIT_FOR_NUM_OFF",
+    "WAIT_FOR_NUM_CHANGE",
+    "WAIT_FOR_SCROLL_ON",
+    "WAIT_FOR_SCROLL_OFF",
+    "WAIT_FOR_SCROLL_CHANGE",
+    "SAVE_HOST_LOCK_STATE",
+    "RESTORE_HOST_LOCK_STATE",
+    "SAVE_HOST_KEYBOARD_LOCK_STATE",
+    "RESTORE_HOST_KEYBOARD_LOCK_STATE",
+];
+
+/// Parses a DuckyScript 1.0 script for a host using the US layout
+pub fn parse_script(script: &str) -> Result<Vec<Action>, WMSError> {
+    parse_script_with_layout(script, &KeyboardLayout::us())
+}
+
+/// Parses a DuckyScript 1.0 script into a list of actions for a boot keyboard
+///
+/// Supported commands are REM, STRING, STRINGLN, DELAY, DEFAULT_DELAY
+/// (or DEFAULTDELAY) and REPEAT (or REPLAY), which repeats the line before it
+/// up to 10000 times. Any other line is treated as a key combination such as
+/// "CTRL ALT DELETE" or "shift a", which is pressed and then released. A
+/// combination holds any modifiers and up to six other keys at once.
+///
+/// `HOLD <keys>` presses keys and leaves them down until `RELEASE <keys>`, or
+/// `RELEASE` on its own, lets go of them.
+///
+/// `WAIT_FOR_CAPS_ON`, `WAIT_FOR_CAPS_OFF` and `WAIT_FOR_CAPS_CHANGE`, and the
+/// same for `NUM` and `SCROLL`, wait for the host's lock LEDs.
+/// `SAVE_HOST_LOCK_STATE` remembers them and `RESTORE_HOST_LOCK_STATE` toggles
+/// lock keys to put them back (the `HOST_KEYBOARD_LOCK_STATE` spellings work too).
+///
+/// Media and power keys are pressed by name on a line of their own, e.g.
+/// `MK_VOLUP`, `PLAY_PAUSE` or `SYSTEM_SLEEP`, and any other Consumer Page usage
+/// with `CONSUMER <usage>`, e.g. `CONSUMER 0x192`.
+///
+/// Characters are typed using `layout` until the script switches to another
+/// layout with `LAYOUT <name>`, e.g. `LAYOUT de`. Names that aren't built in
+/// are loaded from the host's XKB symbols, e.g. `LAYOUT ch(fr)`.
+///
+/// The whole script is checked before anything is returned, and every bad
+/// line is reported in a single [`WMSError::SyntaxError`].
+pub fn parse_script_with_layout(
+    script: &str,
+    layout: &KeyboardLayout,
+) -> Result<Vec<Action>, WMSError> {
+    parse(script, layout, BOOT_ROLLOVER)
+}
+
+/// Parses a script like [`parse_script_with_layout`] for an N-key rollover keyboard
+///
+/// Chords and held keys aren't limited to six keys. While more than six are
+/// held, hosts using the boot protocol only learn that too many keys are down,
+/// see [`Chord::boot_report`].
+pub fn parse_nkro_script(script: &str, layout: &KeyboardLayout) -> Result<Vec<Action>, WMSError> {
+    parse(script, layout, usize::MAX)
+}
+
+/// Parses one key combination such as "ctrl alt delete" into the keys it presses
+///
+/// Unknown keys and more than six keys other than modifiers are reported like
+/// script errors on line 1.
+pub(crate) fn parse_chord(line: &str, layout: &KeyboardLayout) -> Result<Chord, WMSError> {
+    let mut parser = Parser {
+        errors: Vec::new(),
+        line: 1,
+        layout: layout.clone(),
+        held: KeyState::default(),
+        rollover: BOOT_ROLLOVER,
+    };
+    let tokens = tokenize(line);
+    if tokens.is_empty() {
+        parser.error(1, line, "expected keys, found", None);
+    }
+    match parser.chord(&tokens) {
+        Some(keys) if parser.errors.is_empty() => Ok(keys),
+        _ => Err(WMSError::SyntaxError(parser.errors)),
+    }
+}
+
+fn parse(script: &str, layout: &KeyboardLayout, rollover: usize) -> Result<Vec<Action>, WMSError> {
+    let mut parser = Parser {
+        errors: Vec::new(),
+        line: 0,
+        layout: layout.clone(),
+        held: KeyState::default(),
+        rollover,
+    };
+    let mut actions = Vec::new();
+    let mut default_delay = 0;
+    let mut last: Vec<Action> = Vec::new();
+    let mut saved_locks = false;
+
+    for (i, line) in script.lines().enumerate() {
+        parser.line = i + 1;
+        let tokens = tokenize(line);
+        let Some(&(column, command)) = tokens.first() else {
+            continue;
+        };
+        // Everything after the command and the single space that follows it
+        let arg_column = column + command.chars().count() + 1;
+        let arg = line.trim_start().get(command.len() + 1..).unwrap_or("");
+
+        let mut current = match command.to_uppercase().as_str() {
+            "REM" => continue,
+            "LAYOUT" => {
+                let name = arg.trim();
+                let layout = match KeyboardLayout::by_name(name) {
+                    Some(layout) => Ok(layout),
+                    None => KeyboardLayout::from_xkb_name(name),
+                };
+                match layout {
+                    Ok(layout) => parser.layout = layout,
+                    Err(_) => {
+                        let names = KeyboardLayout::names().iter().copied();
+                        let suggestion = suggest_from(&arg.trim().to_lowercase(), names);
+                        parser.error(arg_column, arg.trim(), "unknown layout", suggestion);
+                    }
+                }
+                continue;
+            }
+            "DEFAULT_DELAY" | "DEFAULTDELAY" => {
+                if let Some(ms) = parser.number(command, arg, arg_column) {
+                    default_delay = ms;
+                }
+                continue;
+            }
+            "REPEAT" | "REPLAY" => {
+                let mut count = parser.number(command, arg, arg_column);
+                if last.is_empty() {
+                    parser.error(column, command, "nothing to repeat before", None);
+                }
+                if count.is_some_and(|count| count > MAX_REPEAT) {
+                    let message = format!("repeat count above the limit of {}:", MAX_REPEAT);
+                    parser.error(arg_column, arg.trim(), &message, None);
+                    count = None;
+                }
+                for _ in 0..count.unwrap_or(0) {
+                    actions.extend_from_slice(&last);
+                }
+                continue;
+            }
+            "SAVE_HOST_LOCK_STATE" | "SAVE_HOST_KEYBOARD_LOCK_STATE" => {
+                saved_locks = true;
+                vec![Action::SaveLockState]
+            }
+            "RESTORE_HOST_LOCK_STATE" | "RESTORE_HOST_KEYBOARD_LOCK_STATE" => {
+                if !saved_locks {
+                    parser.error(column, command, "no lock state saved before", None);
+                }
+                vec![Action::RestoreLockState]
+            }
+            upper if upper.starts_with("WAIT_FOR_") => match led_wait(upper) {
+                Some((leds, wait)) => vec![Action::WaitForLeds(leds, wait)],
+                None => {
+                    let suggestion = suggest_from(upper, COMMANDS.iter().copied());
+                    parser.error(column, command, "unknown command", suggestion);
+                    continue;
+                }
+            },
+            "DELAY" => vec![Action::Delay(
+                parser.number(command, arg, arg_column).unwrap_or(0),
+            )],
+            "STRING" => parser.string(arg, arg_column),
+            "STRINGLN" => {
+                let mut current = parser.string(arg, arg_column);
+                let enter = KeyStroke::new(Key::Enter.usage(), 0).chord();
+                current.push(Action::Press(enter));
+                current.push(Action::Release(enter));
+                current
+            }
+            "HOLD" if tokens.len() == 1 => {
+                parser.error(column, command, "expected keys after", None);
+                continue;
+            }
+            "HOLD" => match parser.chord(&tokens[1..]) {
+                Some(keys) => {
+                    parser.held.apply(&Action::Press(keys));
+                    vec![Action::Press(keys)]
+                }
+                None => continue,
+            },
+            "RELEASE" if tokens.len() == 1 => {
+                parser.held.apply(&Action::ReleaseAll);
+                vec![Action::ReleaseAll]
+            }
+            "RELEASE" => match parser.chord(&tokens[1..]) {
+                Some(keys) => {
+                    parser.held.apply(&Action::Release(keys));
+                    vec![Action::Release(keys)]
+                }
+                None => continue,
+            },
+            "CONSUMER" => match parser.number(command, arg, arg_column) {
+                Some(usage @ 1..=0x3FF) => {
+                    let control = Control::Consumer(usage as u16);
+                    vec![
+                        Action::PressControl(control),
+                        Action::ReleaseControl(control),
+                    ]
+                }
+                Some(_) => {
+                    parser.error(arg_column, arg.trim(), "not a consumer usage:", None);
+                    continue;
+                }
+                None => continue,
+            },
+            _ if Control::from_name(command).is_some() => {
+                if let Some(&(column, token)) = tokens.get(1) {
+                    let message = "media and power keys are pressed alone, found";
+                    parser.error(column, token, message, None);
+                    continue;
+                }
+                let control = Control::from_name(command).unwrap();
+                vec![
+                    Action::PressControl(control),
+                    Action::ReleaseControl(control),
+                ]
+            }
+            _ => parser.combo(&tokens),
+        };
+
+        if default_delay > 0 {
+            current.push(Action::Delay(default_delay));
+        }
+        actions.extend_from_slice(&current);
+        last = current;
+    }
+
+    if parser.errors.is_empty() {
+        Ok(actions)
+    } else {
+        Err(WMSError::SyntaxError(parser.errors))
+    }
+}
+
+/// Parses the LED and state a `WAIT_FOR_` command waits for
+fn led_wait(command: &str) -> Option<(LedState, LedWait)> {
+    let (led, wait) = command.strip_prefix("WAIT_FOR_")?.split_once('_')?;
+    let led = match led {
+        "CAPS" => LedState::CAPS_LOCK,
+        "NUM" => LedState::NUM_LOCK,
+        "SCROLL" => LedState::SCROLL_LOCK,
+        _ => return None,
+    };
+    let wait = match wait {
+        "ON" => LedWait::On,
+        "OFF" => LedWait::Off,
+        "CHANGE" => LedWait::Change,
+        _ => return None,
+    };
+    Some((led, wait))
+}
+
+struct Parser {
+    errors: Vec<ScriptError>,
+    line: usize,
+    layout: KeyboardLayout,
+    /// Keys left down by HOLD
+    held: KeyState,
+    /// Most keys other than modifiers held at once
+    rollover: usize,
+}
+
+impl Parser {
+    fn error(&mut self, column: usize, token: &str, message: &str, suggestion: Option<&str>) {
+        let error = ScriptError::new(self.line, column, token, message, suggestion);
+        self.errors.push(error);
+    }
+
+    /// Parses a decimal or `0x` prefixed hexadecimal number
+    fn number(&mut self, command: &str, arg: &str, column: usize) -> Option<u64> {
+        let parsed = match arg.trim().strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => arg.trim().parse(),
+        };
+        match parsed {
+            Ok(n) => Some(n),
+            Err(_) if arg.trim().is_empty() => {
+                self.error(column - 1, command, "expected a number after", None);
+                None
+            }
+            Err(_) => {
+                self.error(column, arg.trim(), "expected a number, found", None);
+                None
+            }
+        }
+    }
+
+    /// Types a string like [`KeyboardLayout::text_to_actions`], reporting every untypeable character
+    fn string(&mut self, s: &str, column: usize) -> Vec<Action> {
+        let mut actions = vec![Action::BeginText];
+        for (i, c) in s.chars().enumerate() {
+            match self.layout.actions(c) {
+                Some(typed) => actions.extend(typed),
+                None => {
+                    let message = format!("cannot type on the {} layout:", self.layout.name());
+                    self.error(column + i, &c.to_string(), &message, None);
+                }
+            }
+        }
+        actions.push(Action::EndText);
+        actions
+    }
+
+    /// Presses and releases a chord of modifiers and up to six other keys, or more with NKRO
+    fn combo(&mut self, tokens: &[(usize, &str)]) -> Vec<Action> {
+        // A misspelled command would otherwise flag each of its arguments too
+        let (column, first) = tokens[0];
+        if !self.is_key(&first.to_lowercase()) && modifier_bit(&first.to_lowercase()).is_none() {
+            let names = COMMANDS.iter().copied().chain(Control::names());
+            if let Some(command) = suggest_from(&first.to_uppercase(), names) {
+                self.error(column, first, "unknown command", Some(command));
+                return Vec::new();
+            }
+        }
+
+        match self.chord(tokens) {
+            Some(keys) => vec![Action::Press(keys), Action::Release(keys)],
+            None => Vec::new(),
+        }
+    }
+
+    /// Parses key names into the keys to press, checking they fit alongside any held keys
+    fn chord(&mut self, tokens: &[(usize, &str)]) -> Option<Chord> {
+        let errors = self.errors.len();
+        let mut keys = Chord::default();
+        for &(column, token) in tokens {
+            let name = token.to_lowercase();
+            if let Some(bit) = modifier_bit(&name) {
+                keys = keys.union(&Chord::new(bit, &[]));
+                continue;
+            }
+            let Some(stroke) = self.key_stroke(&name) else {
+                self.error(column, token, "unknown key or command", suggest(&name));
+                continue;
+            };
+            keys = keys.union(&stroke.chord());
+            if keys.len() > self.rollover {
+                self.error(column, token, "more than six keys pressed at once:", None);
+            } else if !self.held.fits(&keys, self.rollover) {
+                self.error(column, token, "more than six keys held at once:", None);
+            }
+        }
+
+        if self.errors.len() > errors {
+            return None;
+        }
+        Some(keys)
+    }
+
+    /// Looks up a key name, single characters name whichever key types them on the host layout
+    fn key_stroke(&self, name: &str) -> Option<KeyStroke> {
+        match self.layout_key(name) {
+            Some(stroke) => Some(stroke),
+            None if name.chars().count() > 1 => key_code(name).map(|code| KeyStroke::new(code, 0)),
+            None => None,
+        }
+    }
+
+    fn layout_key(&self, name: &str) -> Option<KeyStroke> {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => match self.layout.get(c) {
+                Some(Mapping {
+                    stroke,
+                    dead_key: None,
+                }) => Some(*stroke),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn is_key(&self, name: &str) -> bool {
+        self.key_stroke(name).is_some()
+    }
+}
+
+/// Splits a line on whitespace, keeping the 1-based column of each token
+pub(crate) fn tokenize(line: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (column, (i, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((column, i)),
+            (true, Some((start_column, start_i))) => {
+                tokens.push((start_column + 1, &line[start_i..i]));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some((start_column, start_i)) = start {
+        tokens.push((start_column + 1, &line[start_i..]));
+    }
+    tokens
+}
+
+/// Finds the closest modifier or key name to a misspelled token
+fn suggest(token: &str) -> Option<&'static str> {
+    // Single characters are too short to guess at
+    let names = Key::ALL
+        .iter()
+        .flat_map(|key| key.names().iter().copied())
+        .filter(|name| name.len() > 1);
+    suggest_from(token, names)
+}
+
+pub(crate) fn suggest_from<'a>(
+    token: &str,
+    candidates: impl Iterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .map(|c| (edit_distance(token, c), c))
+        .filter(|(d, _)| *d <= 2 && *d < token.len())
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(script: &str) -> Vec<ScriptError> {
+        match parse_script(script) {
+            Err(WMSError::SyntaxError(errors)) => errors,
+            other => panic!("expected syntax errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn combo_presses_and_releases() {
+        let keys = Chord::new(0x05, &[Key::Delete.usage()]);
+        assert_eq!(
+            parse_script("CTRL ALT DELETE").unwrap(),
+            vec![Action::Press(keys), Action::Release(keys)]
+        );
+    }
+
+    #[test]
+    fn comments_and_delays() {
+        let actions = parse_script("REM nothing to see\n\nDELAY 250\nDELAY 0x10").unwrap();
+        assert_eq!(actions, vec![Action::Delay(250), Action::Delay(16)]);
+    }
+
+    #[test]
+    fn stringln_ends_with_enter() {
+        let enter = Chord::new(0, &[Key::Enter.usage()]);
+        let actions = parse_script("STRINGLN a").unwrap();
+        assert_eq!(actions.first(), Some(&Action::BeginText));
+        assert_eq!(
+            actions[actions.len() - 2..],
+            [Action::Press(enter), Action::Release(enter)]
+        );
+    }
+
+    #[test]
+    fn lock_led_commands() {
+        let actions = parse_script(
+            "SAVE_HOST_LOCK_STATE\nWAIT_FOR_CAPS_ON\nwait_for_num_change\nRESTORE_HOST_LOCK_STATE",
+        )
+        .unwrap();
+        let expected = [
+            Action::SaveLockState,
+            Action::WaitForLeds(LedState::CAPS_LOCK, LedWait::On),
+            Action::WaitForLeds(LedState::NUM_LOCK, LedWait::Change),
+            Action::RestoreLockState,
+        ];
+        assert_eq!(actions, expected);
+        assert_eq!(errors("WAIT_FOR_CAPS_UP")[0].token, "WAIT_FOR_CAPS_UP");
+        assert_eq!(errors("RESTORE_HOST_LOCK_STATE")[0].line, 1);
+    }
+
+    #[test]
+    fn control_keys() {
+        let volume = Control::Consumer(0xE9);
+        let actions = parse_script("MK_VOLUP\nCONSUMER 0x18A").unwrap();
+        let mail = Control::Consumer(0x18A);
+        let expected = [
+            Action::PressControl(volume),
+            Action::ReleaseControl(volume),
+            Action::PressControl(mail),
+            Action::ReleaseControl(mail),
+        ];
+        assert_eq!(actions, expected);
+        assert_eq!(errors("MK_VOLUP a")[0].token, "a");
+        assert_eq!(errors("CONSUMER 0x400")[0].token, "0x400");
+        assert_eq!(
+            errors("MK_VOLUPP")[0].suggestion.as_deref(),
+            Some("MK_VOLUP")
+        );
+    }
+
+    #[test]
+    fn repeat_and_default_delay() {
+        let a = Chord::new(0, &[0x04]);
+        let once = vec![Action::Press(a), Action::Release(a), Action::Delay(10)];
+        let actions = parse_script("DEFAULT_DELAY 10\na\nREPEAT 2").unwrap();
+        assert_eq!(actions, [once.clone(), once.clone(), once].concat());
+    }
+
+    #[test]
+    fn repeat_over_limit() {
+        let errors = errors("a\nREPEAT 10001");
+        assert_eq!((errors[0].line, errors[0].column), (2, 8));
+        assert_eq!(errors[0].token, "10001");
+        assert!(parse_script("a\nREPEAT 10000").is_ok());
+    }
+
+    #[test]
+    fn repeat_needs_a_line_before() {
+        assert_eq!(errors("REPEAT 3")[0].token, "REPEAT");
+    }
+
+    #[test]
+    fn every_error_has_its_column() {
+        let errors = errors("STRING ok\n  DELAYY 100\nctrl entr\nDELAY x");
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.column, e.token.as_str(), e.suggestion.as_deref()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, 3, "DELAYY", Some("DELAY")),
+                (3, 6, "entr", Some("enter")),
+                (4, 7, "x", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn untypeable_character_column() {
+        let errors = errors("STRING a€b");
+        assert_eq!((errors[0].column, errors[0].token.as_str()), (9, "€"));
+    }
+
+    #[test]
+    fn six_keys_at_once() {
+        let keys = Chord::new(0x01, &[0x04, 0x05, 0x06, 0x07, 0x08, 0x09]);
+        assert_eq!(
+            parse_script("ctrl a b c d e f").unwrap(),
+            vec![Action::Press(keys), Action::Release(keys)]
+        );
+        assert_eq!(errors("a b c d e f g")[0].token, "g");
+        let errors = errors("HOLD a b c d\nHOLD e f g");
+        assert_eq!((errors[0].line, errors[0].token.as_str()), (2, "g"));
+    }
+
+    #[test]
+    fn chords() {
+        let us = KeyboardLayout::us();
+        assert_eq!(
+            parse_chord("shift ctrl a", &us).unwrap().boot_report(),
+            [0x03, 0, 0x04, 0, 0, 0, 0, 0]
+        );
+        for line in ["", "ctrl foo", "a b c d e f g"] {
+            assert!(matches!(
+                parse_chord(line, &us),
+                Err(WMSError::SyntaxError(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::rawhid::hex;
+
+/// Destination for the HID reports a device sends to the host
+pub trait ReportSink: Send {
+    /// Sends one report
+    fn send_report(&mut self, report: &[u8]) -> std::io::Result<()>;
+}
+
+/// Sends reports to the host through a gadget's `/dev/hidgN` character device
+pub struct HidgSink {
+    file: std::fs::File,
+}
+
+impl HidgSink {
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<HidgSink> {
+        let file = std::fs::File::options()
+            .read(false)
+            .write(true)
+            .open(path)?;
+        Ok(HidgSink { file })
+    }
+}
+
+impl ReportSink for HidgSink {
+    fn send_report(&mut self, report: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(report)
+    }
+}
+
+/// Keeps reports in memory, so they can be checked without a UDC
+///
+/// Clones share the same reports, so a clone can be handed to a device and the
+/// original inspected afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    reports: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Returns the reports sent so far
+    pub fn reports(&self) -> Vec<Vec<u8>> {
+        self.reports.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.reports.lock().unwrap().clear();
+    }
+}
+
+impl ReportSink for Recorder {
+    fn send_report(&mut self, report: &[u8]) -> std::io::Result<()> {
+        self.reports.lock().unwrap().push(report.to_vec());
+        Ok(())
+    }
+}
+
+/// Writes reports to a file, one line of hex bytes per report
+pub struct FileCapture {
+    file: std::fs::File,
+}
+
+impl FileCapture {
+    pub fn create(path: impl AsRef<std::path::Path>) -> std::io::Result<FileCapture> {
+        Ok(FileCapture {
+            file: std::fs::File::create(path)?,
+        })
+    }
+}
+
+impl ReportSink for FileCapture {
+    fn send_report(&mut self, report: &[u8]) -> std::io::Result<()> {
+        writeln!(self.file, "{}", hex(report, " "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorder_clones_share_reports() {
+        let recorder = Recorder::new();
+        let mut sink = recorder.clone();
+        sink.send_report(&[1, 2]).unwrap();
+        sink.send_report(&[3]).unwrap();
+        assert_eq!(recorder.reports(), vec![vec![1, 2], vec![3]]);
+        recorder.clear();
+        assert!(sink.reports().is_empty());
+    }
+
+    #[test]
+    fn file_capture_writes_hex_lines() {
+        let path = std::env::temp_dir().join(format!("wms-capture-{}", std::process::id()));
+        let mut capture = FileCapture::create(&path).unwrap();
+        capture.send_report(&[0x02, 0, 0x04]).unwrap();
+        capture.send_report(&[0xff]).unwrap();
+        drop(capture);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "02 00 04\nff\n");
+        std::fs::remove_file(path).unwrap();
+    }
+}
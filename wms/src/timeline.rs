@@ -0,0 +1,222 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::rawhid::hex;
+use crate::{nkro_report, Action, Chord, Control, Key, KeyState, Timing};
+
+/// One step of a dry run, see [`Timeline::compile`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineEntry {
+    /// When the step happens, from the start of the script
+    pub at: Duration,
+    /// HID function the report goes to, `keyboard` or `control`
+    pub function: &'static str,
+    /// Report sent, empty for steps that only wait on the host
+    pub report: Vec<u8>,
+    /// What the report does, e.g. `shift+a` for the keys held
+    pub description: String,
+}
+
+/// The reports a script sends and when, worked out without a UDC
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Timeline {
+    pub entries: Vec<TimelineEntry>,
+    /// Time from the first report until the script is done
+    pub total: Duration,
+}
+
+impl Timeline {
+    /// Works out the reports actions send with `timing`, as boot or NKRO reports
+    ///
+    /// Waits on the host's LEDs are listed but count as no time, and CapsLock
+    /// compensation is left out, as both depend on the host.
+    pub fn compile(actions: &[Action], timing: &Timing, nkro: bool) -> Timeline {
+        let mut timeline = Timeline::default();
+        let mut state = KeyState::default();
+        let mut at = Duration::ZERO;
+        for action in actions {
+            let report = state.apply(action);
+            let (function, report, description) = match (action, report) {
+                (_, Some(keys)) => ("keyboard", encode(&keys, nkro), describe(&keys)),
+                (Action::PressControl(control), _) => (
+                    "control",
+                    control.report(true),
+                    format!("press {}", name(*control)),
+                ),
+                (Action::ReleaseControl(control), _) => (
+                    "control",
+                    control.report(false),
+                    format!("release {}", name(*control)),
+                ),
+                (Action::WaitForLeds(leds, wait), _) => (
+                    "keyboard",
+                    Vec::new(),
+                    format!("wait for LEDs {:#04x} {:?}", leds.bits(), wait),
+                ),
+                _ => {
+                    at += timing.scale(timing.wait_after(action, false));
+                    continue;
+                }
+            };
+            timeline.entries.push(TimelineEntry {
+                at,
+                function,
+                report,
+                description,
+            });
+            at += timing.scale(timing.wait_after(action, true));
+        }
+        // Everything is released at the end, as when injecting
+        timeline.entries.push(TimelineEntry {
+            at,
+            function: "keyboard",
+            report: encode(&Chord::default(), nkro),
+            description: describe(&Chord::default()),
+        });
+        timeline.total = at;
+        timeline
+    }
+
+    /// Formats the timeline as a table, one step per line
+    pub fn to_table(&self) -> String {
+        let mut table = format!(
+            "{:>10}  {:<8}  {:<23}  {}\n",
+            "time", "function", "report", "description"
+        );
+        for entry in &self.entries {
+            let _ = writeln!(
+                table,
+                "{:>9.3}s  {:<8}  {:<23}  {}",
+                entry.at.as_secs_f64(),
+                entry.function,
+                hex(&entry.report, " "),
+                entry.description
+            );
+        }
+        let _ = writeln!(table, "total {:.3}s", self.total.as_secs_f64());
+        table
+    }
+
+    /// Formats the timeline as JSON, with times in milliseconds and reports in hex
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let report = hex(&entry.report, "");
+                format!(
+                    "{{\"at_ms\":{},\"function\":\"{}\",\"report\":\"{}\",\"description\":\"{}\"}}",
+                    entry.at.as_secs_f64() * 1000.0,
+                    entry.function,
+                    report,
+                    entry.description.replace('\\', "\\\\").replace('"', "\\\"")
+                )
+            })
+            .collect();
+        format!(
+            "{{\"total_ms\":{},\"entries\":[{}]}}",
+            self.total.as_secs_f64() * 1000.0,
+            entries.join(",")
+        )
+    }
+}
+
+impl std::fmt::Display for Timeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.to_table())
+    }
+}
+
+fn encode(keys: &Chord, nkro: bool) -> Vec<u8> {
+    match nkro {
+        true => nkro_report(keys),
+        false => keys.boot_report().to_vec(),
+    }
+}
+
+/// Names the keys held with [`Key::name`], modifiers first, e.g. `ctrl+alt+delete`
+fn describe(keys: &Chord) -> String {
+    let held: Vec<String> = (0xE0..=0xE7)
+        .chain(keys.usages())
+        .filter(|&usage| keys.contains(usage))
+        .filter_map(Key::from_usage)
+        .map(|key| key.name().to_string())
+        .collect();
+    match held.is_empty() {
+        true => "release all".to_string(),
+        false => held.join("+"),
+    }
+}
+
+fn name(control: Control) -> String {
+    match control.name() {
+        Some(name) => name.to_string(),
+        None => format!("{:?}", control),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_script;
+
+    fn timing() -> Timing {
+        Timing::new(Duration::from_millis(10), Duration::from_millis(5))
+    }
+
+    #[test]
+    fn reports_and_times() {
+        let actions = parse_script("STRING aB\nDELAY 100\nMK_MUTE").unwrap();
+        let timeline = Timeline::compile(&actions, &timing(), false);
+        let steps: Vec<_> = timeline
+            .entries
+            .iter()
+            .map(|e| (e.at.as_millis(), e.function, e.description.as_str()))
+            .collect();
+        let expected = [
+            (0, "keyboard", "a"),
+            (10, "keyboard", "release all"),
+            (15, "keyboard", "shift"),
+            (25, "keyboard", "shift+b"),
+            (35, "keyboard", "release all"),
+            (140, "control", "press MK_MUTE"),
+            (150, "control", "release MK_MUTE"),
+            (155, "keyboard", "release all"),
+        ];
+        assert_eq!(steps, expected);
+        assert_eq!(timeline.entries[3].report, [0x02, 0, 0x05, 0, 0, 0, 0, 0]);
+        assert_eq!(timeline.total, Duration::from_millis(155));
+    }
+
+    #[test]
+    fn durations_match_the_timeline() {
+        let actions = parse_script("STRING hello\nDELAY 250\nctrl alt delete").unwrap();
+        for speed in [1.0, 2.0, 0.5] {
+            let timing = timing().with_speed(speed).unwrap();
+            let total = Timeline::compile(&actions, &timing, false).total;
+            assert_eq!(timing.duration(&actions), total);
+            assert_eq!(total, Duration::from_millis(340).div_f64(speed));
+        }
+        assert_eq!(timing().duration(&[]), Duration::ZERO);
+    }
+
+    #[test]
+    fn formats() {
+        let actions = parse_script("a").unwrap();
+        let nkro = Timeline::compile(&actions, &timing(), true);
+        assert_eq!(
+            nkro.entries[0].report[..9],
+            [0, 0, 0x04, 0, 0, 0, 0, 0, 0x10]
+        );
+        assert_eq!(nkro.entries[0].report.len(), 36);
+
+        let timeline = Timeline::compile(&actions, &timing(), false);
+        let table = timeline.to_table();
+        let line = "    0.000s  keyboard  00 00 04 00 00 00 00 00  a";
+        assert_eq!(table.lines().nth(1), Some(line));
+        assert_eq!(table.lines().last(), Some("total 0.015s"));
+        let json = timeline.to_json();
+        assert!(json.starts_with("{\"total_ms\":15,\"entries\":[{\"at_ms\":0,"));
+        assert!(json.ends_with("\"description\":\"release all\"}]}"));
+    }
+}
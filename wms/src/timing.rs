@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+
+use crate::{Action, KillSwitch, Timeline, WMSError};
+
+/// Default time each key is held down, and the wait after it is released
+const REPORT_DELAY: Duration = Duration::from_millis(100);
+
+/// How long keys are held and how long to wait between them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    /// Time each key is held down
+    pub hold: Duration,
+    /// Wait after each key is released
+    pub gap: Duration,
+    /// Multiplier for every wait, including script delays. 2.0 runs twice as fast
+    speed: f64,
+}
+
+impl Timing {
+    pub fn new(hold: Duration, gap: Duration) -> Timing {
+        Timing {
+            hold,
+            gap,
+            speed: 1.0,
+        }
+    }
+
+    /// Returns the same timing running `speed` times as fast
+    ///
+    /// Fails unless `speed` is a positive, finite number.
+    pub fn with_speed(self, speed: f64) -> Result<Timing, WMSError> {
+        if !(speed.is_finite() && speed > 0.0) {
+            return Err(WMSError::TimingError(format!(
+                "invalid speed multiplier {}",
+                speed
+            )));
+        }
+        Ok(Timing { speed, ..self })
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Scales a nominal wait by the speed multiplier
+    pub fn scale(&self, wait: Duration) -> Duration {
+        wait.div_f64(self.speed)
+    }
+
+    /// Returns the wait after an action has been sent, before scaling
+    ///
+    /// Actions that send no report only wait for their own delay. Waits on the
+    /// host's LEDs can't be known in advance and count as no time.
+    pub(crate) fn wait_after(&self, action: &Action, sent: bool) -> Duration {
+        match action {
+            Action::Delay(ms) => Duration::from_millis(*ms),
+            Action::Press(_) if sent => self.hold,
+            Action::Release(_) | Action::ReleaseAll if sent => self.gap,
+            Action::PressControl(_) => self.hold,
+            Action::ReleaseControl(_) => self.gap,
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Returns how long it takes to send the actions with this timing
+    ///
+    /// This is the total of [`Timeline::compile`], so both always agree.
+    pub fn duration(&self, actions: &[Action]) -> Duration {
+        Timeline::compile(actions, self, false).total
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing::new(REPORT_DELAY, REPORT_DELAY)
+    }
+}
+
+/// Waits against deadlines measured from a fixed start
+///
+/// Time spent writing reports is absorbed by the next wait instead of adding
+/// up, so a payload takes the same time on every run.
+pub(crate) struct Schedule<'a> {
+    start: Instant,
+    elapsed: Duration,
+    switch: &'a KillSwitch,
+}
+
+impl<'a> Schedule<'a> {
+    pub(crate) fn start(switch: &'a KillSwitch) -> Schedule<'a> {
+        Schedule {
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            switch,
+        }
+    }
+
+    /// Starts measuring deadlines again from now, e.g. after waiting on the host
+    pub(crate) fn restart(&mut self) {
+        self.start = Instant::now();
+        self.elapsed = Duration::ZERO;
+    }
+
+    /// Sleeps until `wait` after the previous deadline
+    ///
+    /// Returns `false` if the kill switch was paused before then, the rest of the
+    /// wait is left to [`Schedule::sleep`] after [`Schedule::pause`].
+    pub(crate) fn wait(&mut self, wait: Duration) -> Result<bool, WMSError> {
+        self.elapsed += wait;
+        self.sleep()
+    }
+
+    /// Sleeps until the deadline of the last wait
+    pub(crate) fn sleep(&mut self) -> Result<bool, WMSError> {
+        self.switch.sleep_until(self.start + self.elapsed)
+    }
+
+    /// Blocks while the kill switch is paused, moving deadlines later by as long
+    pub(crate) fn pause(&mut self) -> Result<(), WMSError> {
+        let paused = Instant::now();
+        self.switch.wait_resumed()?;
+        self.start += paused.elapsed();
+        Ok(())
+    }
+
+    /// Like [`Schedule::wait`], holding still through pauses
+    pub(crate) fn wait_out(&mut self, wait: Duration) -> Result<(), WMSError> {
+        let mut done = self.wait(wait)?;
+        while !done {
+            self.pause()?;
+            done = self.sleep()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_scales_every_wait() {
+        let timing = Timing::default().with_speed(4.0).unwrap();
+        assert_eq!(timing.speed(), 4.0);
+        assert_eq!(
+            timing.scale(Duration::from_millis(100)),
+            Duration::from_millis(25)
+        );
+        assert_eq!(timing.hold, REPORT_DELAY);
+    }
+
+    #[test]
+    fn bad_speeds() {
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let result = Timing::default().with_speed(speed);
+            assert!(matches!(result, Err(WMSError::TimingError(_))), "{}", speed);
+        }
+    }
+
+    #[test]
+    fn waits_after_actions() {
+        let timing = Timing::new(Duration::from_millis(10), Duration::from_millis(20));
+        let keys = crate::Chord::new(0, &[0x04]);
+        assert_eq!(timing.wait_after(&Action::Press(keys), true), timing.hold);
+        assert_eq!(timing.wait_after(&Action::Release(keys), true), timing.gap);
+        // A release that changes nothing sends no report and doesn't wait
+        assert_eq!(
+            timing.wait_after(&Action::Release(keys), false),
+            Duration::ZERO
+        );
+        assert_eq!(
+            timing.wait_after(&Action::Delay(5), false),
+            Duration::from_millis(5)
+        );
+    }
+
+    #[test]
+    fn schedule_keeps_deadlines() {
+        let switch = KillSwitch::new();
+        let mut schedule = Schedule::start(&switch);
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(20));
+        // Time already spent counts towards the next wait
+        assert!(schedule.wait(Duration::from_millis(30)).unwrap());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(30) && elapsed < Duration::from_millis(200));
+    }
+}
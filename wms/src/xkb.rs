@@ -0,0 +1,652 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::layout::{Dead, Levels, COMMON, LEVEL_MODIFIERS, N};
+use crate::{KeyboardLayout, WMSError};
+
+/// Where XKB symbols files are installed on most Linux systems
+pub const XKB_SYMBOLS_DIR: &str = "/usr/share/X11/xkb/symbols";
+
+/// Stops include cycles between symbols files
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// XKB key names on the alphanumeric block and their HID usage IDs
+const KEY_NAMES: [(&str, u8); 48] = [
+    ("TLDE", 0x35),
+    ("AE01", 0x1E),
+    ("AE02", 0x1F),
+    ("AE03", 0x20),
+    ("AE04", 0x21),
+    ("AE05", 0x22),
+    ("AE06", 0x23),
+    ("AE07", 0x24),
+    ("AE08", 0x25),
+    ("AE09", 0x26),
+    ("AE10", 0x27),
+    ("AE11", 0x2D),
+    ("AE12", 0x2E),
+    ("AD01", 0x14),
+    ("AD02", 0x1A),
+    ("AD03", 0x08),
+    ("AD04", 0x15),
+    ("AD05", 0x17),
+    ("AD06", 0x1C),
+    ("AD07", 0x18),
+    ("AD08", 0x0C),
+    ("AD09", 0x12),
+    ("AD10", 0x13),
+    ("AD11", 0x2F),
+    ("AD12", 0x30),
+    ("AC01", 0x04),
+    ("AC02", 0x16),
+    ("AC03", 0x07),
+    ("AC04", 0x09),
+    ("AC05", 0x0A),
+    ("AC06", 0x0B),
+    ("AC07", 0x0D),
+    ("AC08", 0x0E),
+    ("AC09", 0x0F),
+    ("AC10", 0x33),
+    ("AC11", 0x34),
+    ("AB01", 0x1D),
+    ("AB02", 0x1B),
+    ("AB03", 0x06),
+    ("AB04", 0x19),
+    ("AB05", 0x05),
+    ("AB06", 0x11),
+    ("AB07", 0x10),
+    ("AB08", 0x36),
+    ("AB09", 0x37),
+    ("AB10", 0x38),
+    ("LSGT", 0x64),
+    ("SPCE", 0x2C),
+];
+
+/// Keysym names for the printable Latin-1 characters that aren't their own name
+const KEYSYMS: &[(&str, char)] = &[
+    ("space", ' '),
+    ("exclam", '!'),
+    ("quotedbl", '"'),
+    ("numbersign", '#'),
+    ("dollar", '$'),
+    ("percent", '%'),
+    ("ampersand", '&'),
+    ("apostrophe", '\''),
+    ("quoteright", '\''),
+    ("parenleft", '('),
+    ("parenright", ')'),
+    ("asterisk", '*'),
+    ("plus", '+'),
+    ("comma", ','),
+    ("minus", '-'),
+    ("period", '.'),
+    ("slash", '/'),
+    ("colon", ':'),
+    ("semicolon", ';'),
+    ("less", '<'),
+    ("equal", '='),
+    ("greater", '>'),
+    ("question", '?'),
+    ("at", '@'),
+    ("bracketleft", '['),
+    ("backslash", '\\'),
+    ("bracketright", ']'),
+    ("asciicircum", '^'),
+    ("underscore", '_'),
+    ("grave", '`'),
+    ("quoteleft", '`'),
+    ("braceleft", '{'),
+    ("bar", '|'),
+    ("braceright", '}'),
+    ("asciitilde", '~'),
+    ("nobreakspace", '\u{a0}'),
+    ("exclamdown", '¡'),
+    ("cent", '¢'),
+    ("sterling", '£'),
+    ("currency", '¤'),
+    ("yen", '¥'),
+    ("brokenbar", '¦'),
+    ("section", '§'),
+    ("diaeresis", '¨'),
+    ("copyright", '©'),
+    ("ordfeminine", 'ª'),
+    ("guillemotleft", '«'),
+    ("guillemetleft", '«'),
+    ("notsign", '¬'),
+    ("hyphen", '\u{ad}'),
+    ("registered", '®'),
+    ("macron", '¯'),
+    ("degree", '°'),
+    ("plusminus", '±'),
+    ("twosuperior", '²'),
+    ("threesuperior", '³'),
+    ("acute", '´'),
+    ("mu", 'µ'),
+    ("paragraph", '¶'),
+    ("periodcentered", '·'),
+    ("cedilla", '¸'),
+    ("onesuperior", '¹'),
+    ("masculine", 'º'),
+    ("ordmasculine", 'º'),
+    ("guillemotright", '»'),
+    ("guillemetright", '»'),
+    ("onequarter", '¼'),
+    ("onehalf", '½'),
+    ("threequarters", '¾'),
+    ("questiondown", '¿'),
+    ("Agrave", 'À'),
+    ("Aacute", 'Á'),
+    ("Acircumflex", 'Â'),
+    ("Atilde", 'Ã'),
+    ("Adiaeresis", 'Ä'),
+    ("Aring", 'Å'),
+    ("AE", 'Æ'),
+    ("Ccedilla", 'Ç'),
+    ("Egrave", 'È'),
+    ("Eacute", 'É'),
+    ("Ecircumflex", 'Ê'),
+    ("Ediaeresis", 'Ë'),
+    ("Igrave", 'Ì'),
+    ("Iacute", 'Í'),
+    ("Icircumflex", 'Î'),
+    ("Idiaeresis", 'Ï'),
+    ("ETH", 'Ð'),
+    ("Eth", 'Ð'),
+    ("Ntilde", 'Ñ'),
+    ("Ograve", 'Ò'),
+    ("Oacute", 'Ó'),
+    ("Ocircumflex", 'Ô'),
+    ("Otilde", 'Õ'),
+    ("Odiaeresis", 'Ö'),
+    ("multiply", '×'),
+    ("Oslash", 'Ø'),
+    ("Ooblique", 'Ø'),
+    ("Ugrave", 'Ù'),
+    ("Uacute", 'Ú'),
+    ("Ucircumflex", 'Û'),
+    ("Udiaeresis", 'Ü'),
+    ("Yacute", 'Ý'),
+    ("THORN", 'Þ'),
+    ("Thorn", 'Þ'),
+    ("ssharp", 'ß'),
+    ("agrave", 'à'),
+    ("aacute", 'á'),
+    ("acircumflex", 'â'),
+    ("atilde", 'ã'),
+    ("adiaeresis", 'ä'),
+    ("aring", 'å'),
+    ("ae", 'æ'),
+    ("ccedilla", 'ç'),
+    ("egrave", 'è'),
+    ("eacute", 'é'),
+    ("ecircumflex", 'ê'),
+    ("ediaeresis", 'ë'),
+    ("igrave", 'ì'),
+    ("iacute", 'í'),
+    ("icircumflex", 'î'),
+    ("idiaeresis", 'ï'),
+    ("eth", 'ð'),
+    ("ntilde", 'ñ'),
+    ("ograve", 'ò'),
+    ("oacute", 'ó'),
+    ("ocircumflex", 'ô'),
+    ("otilde", 'õ'),
+    ("odiaeresis", 'ö'),
+    ("division", '÷'),
+    ("oslash", 'ø'),
+    ("ooblique", 'ø'),
+    ("ugrave", 'ù'),
+    ("uacute", 'ú'),
+    ("ucircumflex", 'û'),
+    ("udiaeresis", 'ü'),
+    ("yacute", 'ý'),
+    ("thorn", 'þ'),
+    ("ydiaeresis", 'ÿ'),
+    ("EuroSign", '€'),
+    ("oe", 'œ'),
+    ("OE", 'Œ'),
+];
+
+/// Dead keysyms and the accents they add, others are ignored
+const DEAD_KEYSYMS: [(&str, char); 5] = [
+    ("dead_grave", '`'),
+    ("dead_acute", '´'),
+    ("dead_circumflex", '^'),
+    ("dead_tilde", '~'),
+    ("dead_diaeresis", '¨'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sym {
+    None,
+    Char(char),
+    Dead(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    KeyName(String),
+    Punct(char),
+}
+
+/// Key name to the symbols on each of its levels
+type KeyMap = HashMap<String, [Sym; 4]>;
+
+impl KeyboardLayout {
+    /// Loads a layout from an XKB symbols file, e.g. `/usr/share/X11/xkb/symbols/de`
+    ///
+    /// `variant` selects an `xkb_symbols` section of the file, otherwise the one
+    /// marked `default` is used. Files named by `include` statements are looked
+    /// up in the same directory. Only the first group of the alphanumeric keys is
+    /// used, with levels 3 and 4 typed using AltGr.
+    pub fn from_xkb(
+        path: impl AsRef<Path>,
+        variant: Option<&str>,
+    ) -> Result<KeyboardLayout, WMSError> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let file = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .ok_or_else(|| WMSError::LayoutError(format!("bad path {}", path.display())))?;
+
+        let mut keys = KeyMap::new();
+        load_section(dir, file, variant, &mut keys, 0)?;
+        let name = match variant {
+            Some(variant) => format!("{}({})", file, variant),
+            None => file.to_string(),
+        };
+        Ok(to_layout(&name, &keys))
+    }
+
+    /// Loads an installed XKB layout by name, e.g. "ch" or "ch(fr)"
+    pub fn from_xkb_name(name: &str) -> Result<KeyboardLayout, WMSError> {
+        let (file, variant) = split_spec(name);
+        KeyboardLayout::from_xkb(PathBuf::from(XKB_SYMBOLS_DIR).join(file), variant)
+    }
+}
+
+/// Splits "file(variant)" into its parts
+fn split_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('(') {
+        Some((file, variant)) => (file, Some(variant.trim_end_matches(')'))),
+        None => (spec, None),
+    }
+}
+
+fn load_section(
+    dir: &Path,
+    file: &str,
+    variant: Option<&str>,
+    keys: &mut KeyMap,
+    depth: usize,
+) -> Result<(), WMSError> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(WMSError::LayoutError(format!(
+            "includes nested too deeply at {}",
+            file
+        )));
+    }
+    let text = std::fs::read_to_string(dir.join(file)).map_err(WMSError::FileError)?;
+    let tokens = tokenize(&text);
+    let body = find_section(&tokens, variant).ok_or_else(|| {
+        WMSError::LayoutError(format!(
+            "no xkb_symbols \"{}\" in {}",
+            variant.unwrap_or("default"),
+            file
+        ))
+    })?;
+
+    let mut i = 0;
+    while i < body.len() {
+        match &body[i] {
+            Token::Ident(word)
+                if matches!(
+                    word.as_str(),
+                    "include" | "augment" | "override" | "replace"
+                ) && matches!(body.get(i + 1), Some(Token::Str(_))) =>
+            {
+                let Some(Token::Str(specs)) = body.get(i + 1) else {
+                    unreachable!()
+                };
+                for spec in specs.split(['+', '|']).filter(|s| !s.is_empty()) {
+                    let (file, variant) = split_spec(spec);
+                    load_section(dir, file, variant, keys, depth + 1)?;
+                }
+                i += 2;
+            }
+            // Merge mode of the key statement that follows
+            Token::Ident(word) if matches!(word.as_str(), "augment" | "override" | "replace") => {
+                i += 1
+            }
+            Token::Ident(word) if word == "key" => {
+                let Some(Token::KeyName(name)) = body.get(i + 1) else {
+                    i = skip_statement(body, i);
+                    continue;
+                };
+                let end = skip_statement(body, i);
+                let levels = parse_key(&body[i + 2..end]).ok_or_else(|| {
+                    WMSError::LayoutError(format!("unclosed `[` in key <{}> in {}", name, file))
+                })?;
+                let entry = keys.entry(name.clone()).or_insert([Sym::None; 4]);
+                for (level, sym) in levels.into_iter().enumerate().take(4) {
+                    if sym != Sym::None {
+                        entry[level] = sym;
+                    }
+                }
+                i = end;
+            }
+            _ => i = skip_statement(body, i),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the tokens between the braces of the requested section
+fn find_section<'a>(tokens: &'a [Token], variant: Option<&str>) -> Option<&'a [Token]> {
+    let mut sections = Vec::new();
+    let mut is_default = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            Token::Ident(word) if word == "default" => is_default = true,
+            Token::Ident(word) if word == "xkb_symbols" => {
+                let Some(Token::Str(name)) = tokens.get(i + 1) else {
+                    i += 1;
+                    continue;
+                };
+                let start = i + 3;
+                let end = skip_statement(tokens, i + 2);
+                // `end` is past the closing "};", unless the file ends first
+                let closed = matches!(
+                    tokens.get(end.saturating_sub(2)..end),
+                    Some([Token::Punct('}'), Token::Punct(';')])
+                );
+                let body_end = if closed { end - 2 } else { end };
+                if let Some(body) = tokens.get(start..body_end) {
+                    sections.push((name.as_str(), is_default, body));
+                }
+                is_default = false;
+                i = end;
+                continue;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    let section = match variant {
+        Some(variant) => sections.iter().find(|(name, _, _)| *name == variant),
+        None => sections
+            .iter()
+            .find(|(_, is_default, _)| *is_default)
+            .or(sections.first()),
+    };
+    section.map(|(_, _, body)| *body)
+}
+
+/// Returns the index just past the `;` that ends the statement starting at `start`
+fn skip_statement(tokens: &[Token], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Punct('{') | Token::Punct('[') | Token::Punct('(') => depth += 1,
+            Token::Punct('}') | Token::Punct(']') | Token::Punct(')') => depth -= 1,
+            Token::Punct(';') if depth <= 0 => return i + 1,
+            _ => (),
+        }
+        i += 1;
+    }
+    tokens.len()
+}
+
+/// Finds the group 1 symbols in the body of a key statement
+///
+/// Returns `None` if a `[` is never closed, as at the end of a truncated file.
+fn parse_key(tokens: &[Token]) -> Option<Vec<Sym>> {
+    let mut owner: Option<(&str, bool)> = None;
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            // An index such as `symbols[Group1]`
+            Token::Punct('[') if matches!(tokens.get(i.wrapping_sub(1)), Some(Token::Ident(_))) => {
+                let Some(Token::Ident(field)) = tokens.get(i - 1) else {
+                    unreachable!()
+                };
+                let is_group1 = matches!(tokens.get(i + 1), Some(Token::Ident(g)) if g == "Group1");
+                owner = Some((field.as_str(), is_group1));
+                i = skip_list(tokens, i)?;
+            }
+            Token::Punct('[') => {
+                let end = skip_list(tokens, i)?;
+                match owner {
+                    // Lists without a field name are the symbols of each group in turn
+                    None | Some(("symbols", true)) => {
+                        let symbols = tokens[i + 1..end - 1]
+                            .iter()
+                            .filter_map(|t| match t {
+                                Token::Ident(name) => Some(keysym(name)),
+                                _ => None,
+                            })
+                            .collect();
+                        return Some(symbols);
+                    }
+                    _ => (),
+                }
+                owner = None;
+                i = end;
+            }
+            Token::Punct(',') => {
+                owner = None;
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    Some(Vec::new())
+}
+
+/// Returns the index just past the `]` matching the `[` at `start`, if there is one
+fn skip_list(tokens: &[Token], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::Punct('[') => depth += 1,
+            Token::Punct(']') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+fn keysym(name: &str) -> Sym {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Sym::Char(c);
+    }
+    if let Some((_, c)) = KEYSYMS.iter().find(|(n, _)| *n == name) {
+        return Sym::Char(*c);
+    }
+    if let Some((_, accent)) = DEAD_KEYSYMS.iter().find(|(n, _)| *n == name) {
+        return Sym::Dead(*accent);
+    }
+    // Unicode keysyms are written as U20AC or 0x10020ac
+    let code = match (name.strip_prefix('U'), name.strip_prefix("0x")) {
+        (Some(hex), _) if hex.len() >= 4 => u32::from_str_radix(hex, 16).ok(),
+        (_, Some(hex)) => match u32::from_str_radix(hex, 16) {
+            Ok(code) if code >= 0x0100_0000 => Some(code - 0x0100_0000),
+            Ok(code) if code < 0x100 => Some(code),
+            _ => None,
+        },
+        _ => None,
+    };
+    match code.and_then(char::from_u32) {
+        Some(c) if !c.is_control() => Sym::Char(c),
+        _ => Sym::None,
+    }
+}
+
+fn to_layout(name: &str, keys: &KeyMap) -> KeyboardLayout {
+    // ISO keyboards send the non-US # usage for the key next to enter
+    let backslash = if keys.contains_key("LSGT") {
+        0x32
+    } else {
+        0x31
+    };
+    let key_names = KEY_NAMES
+        .iter()
+        .copied()
+        .chain([("BKSL", backslash), ("AC12", backslash)]);
+
+    let mut levels: Vec<Levels> = COMMON
+        .iter()
+        .map(|&(usage, [plain, shift, altgr])| (usage, [plain, shift, altgr, N]))
+        .collect();
+    let mut dead_keys: Vec<Dead> = Vec::new();
+    for (key, usage) in key_names {
+        let Some(syms) = keys.get(key) else {
+            continue;
+        };
+        let mut chars = [N; 4];
+        for (level, sym) in syms.iter().enumerate() {
+            match sym {
+                Sym::Char(c) => chars[level] = *c,
+                Sym::Dead(accent) => dead_keys.push((usage, LEVEL_MODIFIERS[level], *accent)),
+                Sym::None => (),
+            }
+        }
+        levels.retain(|(u, _)| *u != usage);
+        levels.push((usage, chars));
+    }
+    KeyboardLayout::from_levels(name, &levels, &dead_keys)
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                let mut prev = '\0';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '"' => {
+                let s: String = chars.by_ref().take_while(|c| *c != '"').collect();
+                tokens.push(Token::Str(s));
+            }
+            '<' => {
+                let s: String = chars.by_ref().take_while(|c| *c != '>').collect();
+                tokens.push(Token::KeyName(s));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut s = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '.'))
+                {
+                    s.push(c);
+                }
+                tokens.push(Token::Ident(s));
+            }
+            c if c.is_whitespace() => (),
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes symbols files to a directory of their own and loads `file` from it
+    fn load(
+        files: &[(&str, &str)],
+        file: &str,
+        variant: Option<&str>,
+    ) -> Result<KeyboardLayout, WMSError> {
+        let dir = std::env::temp_dir().join(format!("wms-xkb-{}-{}", std::process::id(), file));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        let layout = KeyboardLayout::from_xkb(dir.join(file), variant);
+        std::fs::remove_dir_all(&dir).unwrap();
+        layout
+    }
+
+    fn stroke(layout: &KeyboardLayout, c: char) -> Option<(u8, u8)> {
+        layout.get(c).map(|m| (m.stroke.usage, m.stroke.modifiers))
+    }
+
+    #[test]
+    fn key_levels() {
+        let keys = parse_key(&tokenize("{ [ q, Q, at, U03A9 ] };"));
+        let expected = [
+            Sym::Char('q'),
+            Sym::Char('Q'),
+            Sym::Char('@'),
+            Sym::Char('Ω'),
+        ];
+        assert_eq!(keys, Some(expected.to_vec()));
+        let keys = parse_key(&tokenize(
+            "{ type[Group1] = \"FOUR_LEVEL\", symbols[Group1] = [ e, E ] };",
+        ));
+        assert_eq!(keys, Some(vec![Sym::Char('e'), Sym::Char('E')]));
+        let keys = parse_key(&tokenize("{ [ dead_acute ] };"));
+        assert_eq!(keys, Some(vec![Sym::Dead('´')]));
+    }
+
+    #[test]
+    fn unclosed_lists() {
+        assert_eq!(parse_key(&tokenize("{ [")), None);
+        assert_eq!(parse_key(&tokenize("{ [ z, Z")), None);
+        assert_eq!(parse_key(&tokenize("{ symbols[Group1 = [ z ] };")), None);
+    }
+
+    #[test]
+    fn default_section_and_includes() {
+        let base = "xkb_symbols \"basic\" { key <AD06> { [ y, Y ] }; key <AB01> { [ z, Z ] }; };";
+        let swapped = "default xkb_symbols \"qwertz\" {
+            include \"base(basic)\"
+            key <AD06> { [ z, Z ] };
+            key <AB01> { [ y, Y ] };
+        };";
+        let layout = load(&[("base", base), ("swapped", swapped)], "swapped", None).unwrap();
+        assert_eq!(layout.name(), "swapped");
+        assert_eq!(stroke(&layout, 'z'), Some((0x1C, 0)));
+        assert_eq!(stroke(&layout, 'Y'), Some((0x1D, 0x02)));
+    }
+
+    #[test]
+    fn short_sections_are_skipped() {
+        let symbols = "xkb_symbols \"empty\";
+            xkb_symbols \"basic\" { key <AD01> { [ q, Q, at ] }; };";
+        let layout = load(&[("short", symbols)], "short", Some("basic")).unwrap();
+        assert_eq!(stroke(&layout, '@'), Some((0x14, 0x40)));
+        let layout = load(&[("short", "xkb_symbols {\n")], "short", None);
+        assert!(matches!(layout, Err(WMSError::LayoutError(_))));
+    }
+
+    #[test]
+    fn truncated_file() {
+        let symbols = "xkb_symbols \"basic\" {
+            key <AD01> { [ q, Q ] };
+            key <AD02> { [ w, W";
+        let layout = load(&[("truncated", symbols)], "truncated", None);
+        assert!(matches!(layout, Err(WMSError::LayoutError(message)) if message.contains("AD02")));
+    }
+}